@@ -0,0 +1,37 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pmd_pkdpx::{compress_px_level, decompress_px, naive_compression, CompressionLevel};
+use std::io::Cursor;
+
+/// pseudo-random bytes, that the compressor can't do much with
+fn noise(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x1234_5678;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// something that look like a tilemap, with a lot of repetition
+fn tiles(len: usize) -> Vec<u8> {
+    (0..len).map(|pos| ((pos / 7) % 5 * 0x11) as u8).collect()
+}
+
+fn bench_decompress(c: &mut Criterion) {
+    let literals = naive_compression(Cursor::new(noise(50_000))).unwrap();
+    c.bench_function("decompress literals", |b| {
+        b.iter(|| decompress_px(Cursor::new(black_box(&literals))).unwrap())
+    });
+
+    let compressed =
+        compress_px_level(Cursor::new(tiles(200_000)), CompressionLevel::Best).unwrap();
+    c.bench_function("decompress back-references", |b| {
+        b.iter(|| decompress_px(Cursor::new(black_box(&compressed))).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_decompress);
+criterion_main!(benches);
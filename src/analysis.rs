@@ -0,0 +1,41 @@
+use crate::{PXError, PxHeader};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+/// read the header of every file, and count how many time each value appear in each of the nine control flag slots.
+///
+/// Only the headers are read. The first error encountered is returned.
+pub fn aggregate_control_flags<I, F>(files: I) -> Result<[HashMap<u8, u64>; 9], PXError>
+where
+    I: Iterator<Item = F>,
+    F: Read + Seek,
+{
+    let mut result: [HashMap<u8, u64>; 9] = Default::default();
+    for mut file in files {
+        let header = PxHeader::parse(&mut file)?;
+        for (slot, flag) in result.iter_mut().zip(header.control_flags.iter()) {
+            *slot.entry(*flag).or_insert(0) += 1;
+        }
+    }
+    Ok(result)
+}
+
+fn read_container<F: Read + Seek>(file: &mut F, header: &PxHeader) -> Result<Vec<u8>, PXError> {
+    let mut container = vec![0; header.container_length as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut container)?;
+    Ok(container)
+}
+
+/// compare the header and compressed data of two px files, ignoring what follow the container length indicated in their header (like padding).
+pub fn px_container_eq<A: Read + Seek, B: Read + Seek>(
+    mut a: A,
+    mut b: B,
+) -> Result<bool, PXError> {
+    let header_a = PxHeader::parse(&mut a)?;
+    let header_b = PxHeader::parse(&mut b)?;
+    if header_a.container_length != header_b.container_length {
+        return Ok(false);
+    };
+    Ok(read_container(&mut a, &header_a)? == read_container(&mut b, &header_b)?)
+}
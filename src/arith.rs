@@ -0,0 +1,106 @@
+//! the arithmetic of the decoder, with the casts and range checks in a single place
+
+use crate::PXError;
+
+/// split a byte into its high and its low nibble
+pub(crate) fn split_nibbles(byte: u8) -> (u8, u8) {
+    (byte >> 4, byte & 0xF)
+}
+
+/// how far back from the end of the output a back-reference start copying, between 1 and 0x1000.
+///
+/// The format store ``0x1000`` minus this distance, the 4 upper bits in the low nibble of the command and the 8 lower bits in the following byte.
+pub(crate) fn backref_distance(nb_low: u8, new_byte: u8) -> usize {
+    0x1000 - (((nb_low & 0xF) as usize) << 8 | new_byte as usize)
+}
+
+/// the number of byte copied by a back-reference, between 3 and 18
+pub(crate) fn backref_length(nb_high: u8) -> usize {
+    (nb_high & 0xF) as usize + 3
+}
+
+/// decode a back-reference, and return the position in the output it start copying from and the number of byte it copy.
+///
+/// Return ``PXError::InvalidBackReference`` if it would start before the start of the output (of length ``out_len``). The position is
+/// computed on ``usize``, so it stay correct for outputs of more than 2 GB.
+pub(crate) fn decode_backref(
+    nb_low: u8,
+    nb_high: u8,
+    new_byte: u8,
+    out_len: usize,
+) -> Result<(usize, usize), PXError> {
+    let distance = backref_distance(nb_low, new_byte);
+    let length = backref_length(nb_high);
+    match out_len.checked_sub(distance) {
+        Some(offset) => Ok((offset, length)),
+        None => Err(PXError::InvalidBackReference {
+            offset: out_len as i64 - distance as i64,
+            len: length,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nibbles() {
+        assert_eq!(split_nibbles(0x00), (0, 0));
+        assert_eq!(split_nibbles(0xFF), (0xF, 0xF));
+        assert_eq!(split_nibbles(0xA5), (0xA, 0x5));
+        assert_eq!(split_nibbles(0x0F), (0, 0xF));
+        assert_eq!(split_nibbles(0xF0), (0xF, 0));
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(backref_distance(0, 0), 0x1000);
+        assert_eq!(backref_distance(0xF, 0xFF), 1);
+        assert_eq!(backref_distance(0xF, 0xFE), 2);
+        assert_eq!(backref_distance(0x1, 0x00), 0xF00);
+        // only the low nibble of ``nb_low`` is used
+        assert_eq!(backref_distance(0xFF, 0xFF), 1);
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(backref_length(0), 3);
+        assert_eq!(backref_length(0xF), 18);
+        assert_eq!(backref_length(0x1F), 18);
+    }
+
+    #[test]
+    fn backref_past_2gb() {
+        let out_len = i32::MAX as usize + 10;
+        assert_eq!(
+            decode_backref(0xF, 0, 0xFF, out_len).unwrap(),
+            (out_len - 1, 3)
+        );
+        assert_eq!(
+            decode_backref(0, 0xF, 0, out_len).unwrap(),
+            (out_len - 0x1000, 18)
+        );
+    }
+
+    #[test]
+    fn backref_extreme_distances() {
+        assert_eq!(decode_backref(0xF, 0, 0xFF, 1).unwrap(), (0, 3));
+        assert_eq!(decode_backref(0, 0, 0, 0x1000).unwrap(), (0, 3));
+    }
+
+    #[test]
+    fn backref_before_the_start() {
+        assert!(matches!(
+            decode_backref(0xF, 0, 0xFF, 0),
+            Err(PXError::InvalidBackReference { offset: -1, len: 3 })
+        ));
+        assert!(matches!(
+            decode_backref(0, 0xF, 0, 0xFFF),
+            Err(PXError::InvalidBackReference {
+                offset: -1,
+                len: 18
+            })
+        ));
+    }
+}
@@ -0,0 +1,59 @@
+//! a command line tool to decompress and compress px files
+
+use pmd_pkdpx::{compress_at4px, compress_px, decompress_px, PxFormat, PxHeader};
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+use std::process::exit;
+
+const USAGE: &str = "usage:
+    pkdpx decompress <in> <out>
+    pkdpx compress <in> <out> [--at4px]
+    pkdpx info <file>";
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args.as_slice() {
+        ["decompress", input, output] => {
+            let file = BufReader::new(File::open(input).map_err(|err| err.to_string())?);
+            let decompressed = decompress_px(file).map_err(|err| err.to_string())?;
+            fs::write(output, decompressed).map_err(|err| err.to_string())
+        }
+        ["compress", input, output, flags @ ..] => {
+            let at4px = match flags {
+                [] => false,
+                ["--at4px"] => true,
+                _ => return Err(USAGE.to_string()),
+            };
+            let data = Cursor::new(fs::read(input).map_err(|err| err.to_string())?);
+            let compressed = if at4px {
+                compress_at4px(data)
+            } else {
+                compress_px(data)
+            }
+            .map_err(|err| err.to_string())?;
+            fs::write(output, compressed).map_err(|err| err.to_string())
+        }
+        ["info", input] => {
+            let mut file = BufReader::new(File::open(input).map_err(|err| err.to_string())?);
+            let header = PxHeader::parse(&mut file).map_err(|err| err.to_string())?;
+            let format = match header.format {
+                PxFormat::Pkdpx => "PKDPX",
+                PxFormat::At4px => "AT4PX",
+            };
+            println!("format: {}", format);
+            println!("container length: {}", header.container_length);
+            println!("decompressed length: {}", header.decompressed_length);
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        exit(1);
+    };
+}
@@ -0,0 +1,38 @@
+use crate::{compress_at4px, compress_px_slice, decompress_px_slice, PXError};
+use std::io::Cursor;
+
+/// a compression format, so different formats can be handled the same way (for example, with a ``Box<dyn PxCodec>`` chosen at runtime)
+pub trait PxCodec {
+    /// decompress a file of this format
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PXError>;
+    /// compress ``data`` to a file of this format
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, PXError>;
+}
+
+/// the PKDPX format. Both PKDPX and AT4PX files can be decompressed, but the compressed files are PKDPX.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PkdpxCodec;
+
+impl PxCodec for PkdpxCodec {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PXError> {
+        decompress_px_slice(data)
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, PXError> {
+        compress_px_slice(data)
+    }
+}
+
+/// the AT4PX format. Both PKDPX and AT4PX files can be decompressed, but the compressed files are AT4PX.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct At4pxCodec;
+
+impl PxCodec for At4pxCodec {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, PXError> {
+        decompress_px_slice(data)
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, PXError> {
+        compress_at4px(Cursor::new(data))
+    }
+}
@@ -0,0 +1,677 @@
+use crate::match_finder::{encodable_match, HashChainMatchFinder, MatchFinder};
+use crate::producer_tag::{stamp_producer_tag, UNUSED_FLAG};
+use crate::{
+    decompress_px_slice, decompress_px_with_options, naive_compression, DecodeOptions, PXError,
+    PxFormat, PxHeader, AT4PX_MAGIC, PKDPX_MAGIC, PX_CONTROL_OPS, WINDOW_SIZE,
+};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// the shortest back-reference the format can encode (nb_high = 0)
+pub(crate) const MIN_MATCH: usize = 3;
+/// the longest back-reference the format can encode (nb_high = 15)
+pub(crate) const MAX_MATCH: usize = 18;
+
+/// cost, in bits, of each kind of command (the command bit + the data bytes)
+const LITERAL_COST: usize = 9;
+const PATTERN_COST: usize = 9;
+const BACKREF_COST: usize = 17;
+
+/// The effort the compressor put into finding a small output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompressionLevel {
+    /// only emit literal bytes, like ``naive_compression``. Fast, but the output is bigger than the input.
+    Naive,
+    /// greedily use the longest back-reference found at each position, only searching the last 1024 bytes
+    Fast,
+    /// find the sequence of command that give the smallest output for the found back-references
+    Best,
+}
+
+impl CompressionLevel {
+    /// all the compression level, from the fastest to the slowest
+    pub const ALL: [CompressionLevel; 3] = [Self::Naive, Self::Fast, Self::Best];
+}
+
+/// a single command of the px stream, as written by the compressor
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    /// the byte following a 0 bit, that correspond to a control flag
+    Pattern(u8),
+    BackRef {
+        distance: usize,
+        length: usize,
+    },
+}
+
+/// what a control flag table allow the compressor to emit
+struct FlagUsage {
+    /// the high nibble to use for each control flag operation, if this operation is reachable
+    pattern_nibble: [Option<u8>; 9],
+    /// if a back-reference of length ``nb_high + 3`` can be encoded
+    length_allowed: [bool; 16],
+}
+
+impl FlagUsage {
+    fn new(control_flags: &[u8; 9]) -> FlagUsage {
+        let mut pattern_nibble = [None; 9];
+        let mut length_allowed = [true; 16];
+        for (index, flag) in control_flags.iter().enumerate() {
+            if *flag >= 16 {
+                continue;
+            };
+            // the decompressor use the first flag that match
+            if !control_flags[..index].contains(flag) {
+                pattern_nibble[index] = Some(*flag);
+            };
+            length_allowed[*flag as usize] = false;
+        }
+        FlagUsage {
+            pattern_nibble,
+            length_allowed,
+        }
+    }
+
+    /// return the command byte encoding the two bytes at the start of ``data``, if possible
+    fn pattern_at(&self, data: &[u8]) -> Option<u8> {
+        if data.len() < 2 {
+            return None;
+        };
+        PX_CONTROL_OPS
+            .iter()
+            .zip(self.pattern_nibble.iter())
+            .filter_map(|(op, nb_high)| {
+                let nb_high = (*nb_high)?;
+                (0..16)
+                    .find(|nb_low| op.apply(*nb_low) == Some((data[0], data[1])))
+                    .map(|nb_low| (nb_high << 4) | nb_low)
+            })
+            .next()
+    }
+
+    /// the longest encodable back-reference length that is no longer than ``max_length``
+    fn usable_length(&self, max_length: usize) -> Option<usize> {
+        (MIN_MATCH..=max_length.min(MAX_MATCH))
+            .rev()
+            .find(|length| self.length_allowed[length - MIN_MATCH])
+    }
+}
+
+/// choose control flags suited to ``data``, to use with ``CompressOptions::control_flags``.
+///
+/// A pattern encode two bytes in a single byte, but the value of its flag can't be used as the length of a back-references anymore.
+/// The data is first compressed without patterns, then an operation get a flag if it could encode more of the remaining literals
+/// than there are back-references with the length it would remove. The flags that aren't needed are set to 0x10, that never match
+/// (and isn't mistaken for a producer tag).
+pub fn choose_control_flags(data: &[u8]) -> [u8; 9] {
+    // flags of 16 or more disable every pattern
+    let tokens = tokenize_fast(
+        data,
+        &FlagUsage::new(&[UNUSED_FLAG; 9]),
+        &mut HashChainMatchFinder::new(),
+    );
+
+    let patterns = FlagUsage::new(&[0, 1, 2, 3, 4, 5, 6, 7, 8]);
+    let mut pattern_count = [0; 9];
+    let mut length_count = [0; 16];
+    let mut pos = 0;
+    let mut literal_run_end = 0;
+    for (token_index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Literal(_) => {
+                let next_is_literal =
+                    matches!(tokens.get(token_index + 1), Some(Token::Literal(_)));
+                // two literals can be replaced by a pattern, if they weren't already counted
+                if next_is_literal && pos >= literal_run_end {
+                    if let Some(byte) = patterns.pattern_at(&data[pos..]) {
+                        pattern_count[(byte >> 4) as usize] += 1;
+                        literal_run_end = pos + 2;
+                    };
+                };
+            }
+            Token::Pattern(_) => (),
+            Token::BackRef { length, .. } => length_count[length - MIN_MATCH] += 1,
+        };
+        pos += token_length(token);
+    }
+
+    let mut operations: Vec<usize> = (0..9).collect();
+    operations.sort_by_key(|operation| std::cmp::Reverse(pattern_count[*operation]));
+    let mut nibbles: Vec<u8> = (0..16).collect();
+    nibbles.sort_by_key(|nibble| length_count[*nibble as usize]);
+
+    let mut control_flags = [None; 9];
+    for (operation, nibble) in operations.iter().zip(nibbles.iter()) {
+        if pattern_count[*operation] <= length_count[*nibble as usize] {
+            break;
+        };
+        control_flags[*operation] = Some(*nibble);
+    }
+    // the flags need a value, use one that never match a nibble, so they don't shadow the chosen ones
+    let mut result = [UNUSED_FLAG; 9];
+    for (flag, chosen) in result.iter_mut().zip(control_flags.iter()) {
+        if let Some(nibble) = chosen {
+            *flag = *nibble;
+        };
+    }
+    result
+}
+
+fn tokenize_fast(data: &[u8], usage: &FlagUsage, finder: &mut dyn MatchFinder) -> Vec<Token> {
+    finder.reset(data);
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let back_ref = encodable_match(data, pos, finder.find_match(data, pos)).and_then(
+            |(distance, length)| usage.usable_length(length).map(|length| (distance, length)),
+        );
+        let pattern = usage.pattern_at(&data[pos..]);
+        let token = match (back_ref, pattern) {
+            // a pattern is cheaper than a back-reference of three bytes
+            (Some((_, MIN_MATCH)), Some(byte)) | (None, Some(byte)) => Token::Pattern(byte),
+            (Some((distance, length)), _) => Token::BackRef { distance, length },
+            (None, None) => Token::Literal(data[pos]),
+        };
+        pos += token_length(&token);
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn tokenize_best(data: &[u8], usage: &FlagUsage, finder: &mut dyn MatchFinder) -> Vec<Token> {
+    finder.reset(data);
+    let matches: Vec<Option<(usize, usize)>> = (0..data.len())
+        .map(|pos| encodable_match(data, pos, finder.find_match(data, pos)))
+        .collect();
+
+    // cost[pos] is the smallest number of bit needed to encode data[pos..]
+    let mut cost = vec![0; data.len() + 1];
+    let mut choice = vec![Token::Literal(0); data.len()];
+    for pos in (0..data.len()).rev() {
+        let mut best_cost = LITERAL_COST + cost[pos + 1];
+        let mut best_token = Token::Literal(data[pos]);
+        if let Some(byte) = usage.pattern_at(&data[pos..]) {
+            if PATTERN_COST + cost[pos + 2] < best_cost {
+                best_cost = PATTERN_COST + cost[pos + 2];
+                best_token = Token::Pattern(byte);
+            }
+        };
+        if let Some((distance, max_length)) = matches[pos] {
+            for length in MIN_MATCH..=max_length {
+                if usage.length_allowed[length - MIN_MATCH]
+                    && BACKREF_COST + cost[pos + length] < best_cost
+                {
+                    best_cost = BACKREF_COST + cost[pos + length];
+                    best_token = Token::BackRef { distance, length };
+                }
+            }
+        };
+        cost[pos] = best_cost;
+        choice[pos] = best_token;
+    }
+
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let token = choice[pos];
+        pos += token_length(&token);
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// the number of decompressed byte a token produce
+fn token_length(token: &Token) -> usize {
+    match token {
+        Token::Literal(_) => 1,
+        Token::Pattern(_) => 2,
+        Token::BackRef { length, .. } => *length,
+    }
+}
+
+/// write the tokens as a PKDPX or AT4PX file
+fn write_px(
+    tokens: &[Token],
+    control_flags: &[u8; 9],
+    decompressed_size: usize,
+    format: PxFormat,
+) -> Result<Vec<u8>, PXError> {
+    let mut result = Vec::new();
+    result.extend_from_slice(match format {
+        PxFormat::Pkdpx => &PKDPX_MAGIC,
+        PxFormat::At4px => &AT4PX_MAGIC,
+    });
+    // container lenght, rewritten later
+    result.extend_from_slice(&[0; 2]);
+    result.extend_from_slice(control_flags);
+    match format {
+        PxFormat::Pkdpx => result.extend_from_slice(&u32::to_le_bytes(decompressed_size as u32)),
+        PxFormat::At4px => {
+            // the decompressed length is stored on 16 bits
+            if decompressed_size > (u16::MAX as usize) {
+                return Err(PXError::TooLongForAt4px(decompressed_size));
+            };
+            result.extend_from_slice(&u16::to_le_bytes(decompressed_size as u16))
+        }
+    };
+
+    for chunk in tokens.chunks(8) {
+        let command_byte_pos = result.len();
+        result.push(0);
+        for (bit_num, token) in chunk.iter().enumerate() {
+            match token {
+                Token::Literal(byte) => {
+                    result[command_byte_pos] |= 0x80 >> bit_num;
+                    result.push(*byte);
+                }
+                Token::Pattern(byte) => result.push(*byte),
+                Token::BackRef { distance, length } => {
+                    let offset = WINDOW_SIZE - distance;
+                    result.push((((length - MIN_MATCH) as u8) << 4) | (offset >> 8) as u8);
+                    result.push(offset as u8);
+                }
+            }
+        }
+    }
+
+    let container_length = result.len();
+    if container_length > (u16::MAX as usize) {
+        return Err(PXError::FileToCompressTooLong(container_length));
+    };
+    result[5..7].copy_from_slice(&u16::to_le_bytes(container_length as u16));
+    while result.len() % 16 != 0 {
+        result.push(0xAA);
+    }
+
+    Ok(result)
+}
+
+/// the parameters of the compressor
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    pub level: CompressionLevel,
+    /// the kind of file to produce. They only differ by their header.
+    pub format: PxFormat,
+    /// the control flags to write in the header, that decide which patterns the compressor can use (see ``choose_control_flags``).
+    /// The flag values used by patterns can't be used to encode back-references. If ``None``, nine 0 are used. Ignored with
+    /// ``CompressionLevel::Naive``.
+    pub control_flags: Option<[u8; 9]>,
+    /// a tag identifying the program that produced the file (up to 8 bytes, each more than 0x10), stored in the last control flags
+    /// (replacing them, but they aren't used with the default flags). It doesn't change how the file decompress, and can be read back with ``read_producer_tag``.
+    pub producer_tag: Option<Vec<u8>>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> CompressOptions {
+        CompressOptions {
+            level: CompressionLevel::Best,
+            format: PxFormat::Pkdpx,
+            control_flags: None,
+            producer_tag: None,
+        }
+    }
+}
+
+/// the number of previous bytes searched for back-references with ``CompressionLevel::Fast``
+const FAST_WINDOW_SIZE: usize = 0x400;
+
+fn compress_data(data: &[u8], options: &CompressOptions) -> Result<Vec<u8>, PXError> {
+    Ok(compress_data_with_stats(data, options)?.0)
+}
+
+fn compress_data_with_stats(
+    data: &[u8],
+    options: &CompressOptions,
+) -> Result<(Vec<u8>, CompressStats), PXError> {
+    let mut finder = match options.level {
+        CompressionLevel::Fast => HashChainMatchFinder::with_max_distance(FAST_WINDOW_SIZE),
+        _ => HashChainMatchFinder::new(),
+    };
+    compress_data_with_finder(data, options, &mut finder)
+}
+
+/// information on how some data was compressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressStats {
+    /// the number of byte that were compressed
+    pub input_len: usize,
+    /// the size of the compressed file, padding included
+    pub output_len: usize,
+    /// the number of byte stored as is
+    pub literal_bytes: usize,
+    /// the number of back-references
+    pub copy_commands: usize,
+    /// the number of two-bytes patterns encoded with a control flag
+    pub pattern_commands: usize,
+}
+
+fn compress_data_with_finder(
+    data: &[u8],
+    options: &CompressOptions,
+    finder: &mut dyn MatchFinder,
+) -> Result<(Vec<u8>, CompressStats), PXError> {
+    let mut stats = CompressStats {
+        input_len: data.len(),
+        output_len: 0,
+        literal_bytes: 0,
+        copy_commands: 0,
+        pattern_commands: 0,
+    };
+    // ``naive_compression`` can't compress an empty file, but the literal tokens below can
+    if options.level == CompressionLevel::Naive
+        && options.format == PxFormat::Pkdpx
+        && !data.is_empty()
+    {
+        let mut result = naive_compression(Cursor::new(data))?;
+        // only literals are used, so every control flag is free
+        if let Some(tag) = &options.producer_tag {
+            let mut control_flags = [0; 9];
+            stamp_producer_tag(&mut control_flags, tag)?;
+            result[7..16].copy_from_slice(&control_flags);
+        };
+        stats.output_len = result.len();
+        stats.literal_bytes = data.len();
+        return Ok((result, stats));
+    };
+    // by default, only the first flag is used (the others are duplicate of it)
+    let mut control_flags = options.control_flags.unwrap_or([0; 9]);
+    if let Some(tag) = &options.producer_tag {
+        stamp_producer_tag(&mut control_flags, tag)?;
+    };
+    let usage = FlagUsage::new(&control_flags);
+    let tokens = match options.level {
+        CompressionLevel::Naive => data.iter().map(|byte| Token::Literal(*byte)).collect(),
+        CompressionLevel::Fast => tokenize_fast(data, &usage, finder),
+        CompressionLevel::Best => tokenize_best(data, &usage, finder),
+    };
+    for token in &tokens {
+        match token {
+            Token::Literal(_) => stats.literal_bytes += 1,
+            Token::Pattern(_) => stats.pattern_commands += 1,
+            Token::BackRef { .. } => stats.copy_commands += 1,
+        }
+    }
+    let result = write_px(&tokens, &control_flags, data.len(), options.format)?;
+    stats.output_len = result.len();
+    Ok((result, stats))
+}
+
+/// compress the input to a PKDPX file, using back-references and the best compression level.
+///
+/// Unlike ``naive_compression``, the output is usually smaller than the input.
+pub fn compress_px<F: Read + Seek>(file: F) -> Result<Vec<u8>, PXError> {
+    compress_px_with_options(file, &CompressOptions::default())
+}
+
+/// compress ``data`` to a PKDPX file, like ``compress_px``
+pub fn compress_px_slice(data: &[u8]) -> Result<Vec<u8>, PXError> {
+    compress_data(data, &CompressOptions::default())
+}
+
+/// compress the input to a PKDPX file, like ``compress_px``, with the given control flags
+pub fn compress_px_with_flags<F: Read + Seek>(
+    file: F,
+    control_flags: [u8; 9],
+) -> Result<Vec<u8>, PXError> {
+    compress_px_with_options(
+        file,
+        &CompressOptions {
+            control_flags: Some(control_flags),
+            ..Default::default()
+        },
+    )
+}
+
+/// compress the input to an AT4PX file, like ``compress_px``.
+///
+/// AT4PX store the decompressed length on 16 bits, so ``PXError::TooLongForAt4px`` is returned if the input is longer than 0xFFFF bytes.
+pub fn compress_at4px<F: Read + Seek>(file: F) -> Result<Vec<u8>, PXError> {
+    compress_px_with_options(
+        file,
+        &CompressOptions {
+            format: PxFormat::At4px,
+            ..Default::default()
+        },
+    )
+}
+
+/// compress the input to a px file, with the given options. The format of the file is ``options.format``.
+pub fn compress_px_with_options<F: Read + Seek>(
+    mut file: F,
+    options: &CompressOptions,
+) -> Result<Vec<u8>, PXError> {
+    file.seek(SeekFrom::Start(0))?;
+    compress_px_from_read(file, options)
+}
+
+/// compress the data read from ``reader`` until its end to a px file, with the given options.
+///
+/// As the compressor need to look back at the data, the whole input is first read in memory.
+pub fn compress_px_from_read<R: Read>(
+    mut reader: R,
+    options: &CompressOptions,
+) -> Result<Vec<u8>, PXError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    compress_data(&data, options)
+}
+
+/// compress the input to a px file, with the given options, using ``finder`` to search for back-references.
+///
+/// The finder isn't used with ``CompressionLevel::Naive``.
+pub fn compress_px_with_finder<F: Read + Seek>(
+    mut file: F,
+    options: &CompressOptions,
+    finder: &mut dyn MatchFinder,
+) -> Result<Vec<u8>, PXError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(compress_data_with_finder(&data, options, finder)?.0)
+}
+
+/// compress the input to a px file, like ``compress_px_with_options``, and also return statistics on the compression
+pub fn compress_px_stats<F: Read + Seek>(
+    mut file: F,
+    options: &CompressOptions,
+) -> Result<(Vec<u8>, CompressStats), PXError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    compress_data_with_stats(&data, options)
+}
+
+/// what ``compress_px_if_smaller`` did with the data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressOutcome {
+    /// the px file, that is smaller than the data
+    Compressed(Vec<u8>),
+    /// compressing the data would not make it smaller, so it's better to store it as is
+    Stored,
+}
+
+/// compress the input to a px file, like ``compress_px_with_options``, but only return it if it is smaller than the input.
+///
+/// The header and the command bytes make incompressible data bigger once compressed, in which case ``CompressOutcome::Stored`` is
+/// returned.
+pub fn compress_px_if_smaller<F: Read + Seek>(
+    mut file: F,
+    options: &CompressOptions,
+) -> Result<CompressOutcome, PXError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let compressed = compress_data(&data, options)?;
+    if compressed.len() < data.len() {
+        Ok(CompressOutcome::Compressed(compressed))
+    } else {
+        Ok(CompressOutcome::Stored)
+    }
+}
+
+/// compress the input to a PKDPX file, using the given compression level.
+pub fn compress_px_level<F: Read + Seek>(
+    file: F,
+    level: CompressionLevel,
+) -> Result<Vec<u8>, PXError> {
+    compress_px_with_options(
+        file,
+        &CompressOptions {
+            level,
+            ..Default::default()
+        },
+    )
+}
+
+/// compress ``data`` at every ``CompressionLevel``, and return the size of the output for each of them (in the order of ``CompressionLevel::ALL``).
+/// A level that can't compress ``data`` (for example, if the output doesn't fit in the container) get its error instead of a size, without
+/// stopping the other levels.
+pub fn compare_levels(data: &[u8]) -> Vec<(CompressionLevel, Result<usize, PXError>)> {
+    CompressionLevel::ALL
+        .iter()
+        .map(|level| {
+            (
+                *level,
+                compress_px_level(Cursor::new(data), *level).map(|compressed| compressed.len()),
+            )
+        })
+        .collect()
+}
+
+/// compress ``data`` with ``compress_px_slice``, decompress the result with ``decompress_px_slice``, and return whether it give back
+/// exactly ``data``. Useful to check the output of the compressor before using it.
+pub fn verify_roundtrip(data: &[u8]) -> Result<bool, PXError> {
+    let compressed = compress_px_slice(data)?;
+    Ok(decompress_px_slice(&compressed)? == data)
+}
+
+/// decompress a px file and compress it back with the best level, to a file of the same format (PKDPX or AT4PX).
+///
+/// The output only depend on the decompressed data and the format, so two files with the same content give the same bytes. The bytes
+/// past the decompressed length indicated in the header are dropped (see ``DecodeOptions::truncate_overshoot``).
+pub fn canonicalize_px<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError> {
+    let format = PxHeader::parse(&mut file)?.format;
+    canonicalize_px_to(file, format)
+}
+
+/// like ``canonicalize_px``, but produce a file of the given format
+pub fn canonicalize_px_to<F: Read + Seek>(file: F, format: PxFormat) -> Result<Vec<u8>, PXError> {
+    let data = decompress_px_with_options(
+        file,
+        &DecodeOptions {
+            truncate_overshoot: true,
+            ..Default::default()
+        },
+    )?;
+    compress_data(
+        &data,
+        &CompressOptions {
+            level: CompressionLevel::Best,
+            format,
+            ..Default::default()
+        },
+    )
+}
+
+/// a compressed px file split in banks of equal size
+#[derive(Debug, Clone)]
+pub struct PxBanks {
+    /// the banks, in order. They all have the same size, with the last one padded with 0xAA.
+    pub banks: Vec<Vec<u8>>,
+    /// the length of the px container, as indicated in its header. Concatenating the banks and keeping this many bytes give back the container.
+    pub container_length: usize,
+}
+
+/// compress ``data`` to a PKDPX file with the default options, and split the result in bank of ``bank_size`` bytes.
+pub fn compress_px_banked(data: &[u8], bank_size: usize) -> Result<PxBanks, PXError> {
+    if bank_size == 0 {
+        return Err(PXError::InvalidBankSize);
+    };
+    let compressed = compress_data(data, &CompressOptions::default())?;
+    let container_length = u16::from_le_bytes([compressed[5], compressed[6]]) as usize;
+    let banks = compressed
+        .chunks(bank_size)
+        .map(|chunk| {
+            let mut bank = chunk.to_vec();
+            bank.resize(bank_size, 0xAA);
+            bank
+        })
+        .collect();
+    Ok(PxBanks {
+        banks,
+        container_length,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ControlFlags;
+
+    /// pairs of bytes that only the fourth operation (``n n``, ``n-1 n``) can encode
+    fn fourth_operation_data() -> Vec<u8> {
+        let mut seed = 0x1234_5678u32;
+        let mut data = Vec::new();
+        for _ in 0..2000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let nibble = ((seed >> 16) % 15 + 1) as u8;
+            data.push(nibble << 4 | nibble);
+            data.push((nibble - 1) << 4 | nibble);
+        }
+        data
+    }
+
+    #[test]
+    fn empty_data_at_every_level() {
+        let sizes = compare_levels(&[]);
+        assert_eq!(sizes.len(), CompressionLevel::ALL.len());
+        assert!(sizes.iter().all(|(_, size)| size.is_ok()));
+        for level in CompressionLevel::ALL.iter() {
+            for format in [PxFormat::Pkdpx, PxFormat::At4px] {
+                let options = CompressOptions {
+                    level: *level,
+                    format,
+                    ..Default::default()
+                };
+                let compressed = compress_data(&[], &options).unwrap();
+                assert!(decompress_px_slice(&compressed).unwrap().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn compare_levels_keeps_going_after_an_error() {
+        // the naive output (one literal per byte) doesn't fit in the 16 bits container length,
+        // but a repeated byte compress well at the other levels
+        let sizes = compare_levels(&[0x55; 60000]);
+        assert_eq!(sizes.len(), CompressionLevel::ALL.len());
+        for (level, size) in sizes {
+            if level == CompressionLevel::Naive {
+                assert!(size.is_err());
+            } else {
+                assert!(size.unwrap() < 60000);
+            };
+        }
+    }
+
+    #[test]
+    fn chosen_operation_is_found() {
+        let data = fourth_operation_data();
+        let flags = choose_control_flags(&data);
+        assert!(flags[3] < 16);
+        assert_eq!(ControlFlags::new(flags).find(flags[3]), Some(3));
+        for (index, flag) in flags.iter().enumerate() {
+            if index != 3 {
+                assert!(*flag >= 16);
+            };
+        }
+
+        let options = CompressOptions {
+            control_flags: Some(flags),
+            ..Default::default()
+        };
+        let (compressed, stats) = compress_data_with_stats(&data, &options).unwrap();
+        assert!(stats.pattern_commands > 0);
+        assert_eq!(decompress_px_slice(&compressed).unwrap(), data);
+    }
+}
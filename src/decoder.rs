@@ -0,0 +1,345 @@
+use crate::{
+    body_reader, output_buffer, reserve_output, BodyDecoder, DecodeOptions, PXError, PxHeader,
+    MAX_COMMAND_OUTPUT,
+};
+use io_partition::Partition;
+use std::io::{self, BufReader, Read, Seek};
+use std::ops::Range;
+
+fn to_io_error(err: PXError) -> io::Error {
+    match err {
+        PXError::IOError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+/// a px decompressor that produce its output on demand, with the ``Read`` trait.
+///
+/// The input is read one command byte at a time, so only the last 4096 decompressed bytes are kept in memory. A corrupted body is
+/// reported as an ``io::Error`` of kind ``InvalidData`` wrapping the ``PXError`` when it is encountered (``PXError::IOError`` are passed as is).
+/// ``decompress_px`` is the same as reading it until the end.
+pub struct PxDecoder<F: Read + Seek> {
+    body: BufReader<Partition<F>>,
+    header: PxHeader,
+    decoder: BodyDecoder,
+    /// the bytes decompressed from the last command byte, not yet read
+    pending: Vec<u8>,
+    pending_position: usize,
+    finished: bool,
+}
+
+impl<F: Read + Seek> PxDecoder<F> {
+    /// parse the header of ``file``, and prepare to decompress its body
+    pub fn new(file: F) -> Result<PxDecoder<F>, PXError> {
+        Self::with_options(file, &DecodeOptions::default())
+    }
+
+    /// same as ``new``, but with the given decoding options
+    pub fn with_options(mut file: F, options: &DecodeOptions) -> Result<PxDecoder<F>, PXError> {
+        let header = PxHeader::parse_with_options(&mut file, options)?;
+        let decoder = BodyDecoder::new(&header, options)?;
+        let (body, _) = body_reader(file)?;
+        Ok(PxDecoder {
+            body,
+            finished: decoder.is_finished(),
+            decoder,
+            header,
+            pending: Vec::with_capacity(8 * MAX_COMMAND_OUTPUT),
+            pending_position: 0,
+        })
+    }
+
+    /// the header of the decompressed file
+    pub fn header(&self) -> &PxHeader {
+        &self.header
+    }
+
+    /// the number of byte the decompressed file is declared to have
+    pub fn decompressed_length(&self) -> u64 {
+        self.header.decompressed_length as u64
+    }
+
+    /// the number of byte of the px file read until now, header included
+    pub fn input_position(&self) -> u64 {
+        self.header.header_length + self.decoder.consumed
+    }
+
+    /// decompress the next command byte into ``pending``. Stop the decompression on error, as it can't continue from a corrupted state.
+    fn refill(&mut self) -> Result<(), PXError> {
+        self.pending.clear();
+        self.pending_position = 0;
+        let result =
+            self.decoder
+                .decode_command_byte(&mut self.body, &mut self.pending, &mut |_, _, _| Ok(()));
+        if result.is_err() {
+            self.pending.clear();
+        };
+        self.finished = result.is_err() || self.decoder.is_finished();
+        result
+    }
+
+    /// like ``Read::read``, but keep the error as a ``PXError``
+    fn read_px(&mut self, buf: &mut [u8]) -> Result<usize, PXError> {
+        while self.pending_position == self.pending.len() {
+            if self.finished {
+                return Ok(0);
+            };
+            self.refill()?;
+        }
+        let available = &self.pending[self.pending_position..];
+        let to_copy = available.len().min(buf.len());
+        buf[..to_copy].copy_from_slice(&available[..to_copy]);
+        self.pending_position += to_copy;
+        Ok(to_copy)
+    }
+
+    /// return the next decompressed byte, or ``None`` at the end of the file
+    fn next_byte(&mut self) -> Result<Option<u8>, PXError> {
+        while self.pending_position == self.pending.len() {
+            if self.finished {
+                return Ok(None);
+            };
+            self.refill()?;
+        }
+        self.pending_position += 1;
+        Ok(Some(self.pending[self.pending_position - 1]))
+    }
+
+    /// decompress everything that is left, appending it to ``out``. Like ``Read::read_to_end``, but keep the error as a ``PXError``.
+    pub(crate) fn read_to_end_px(&mut self, out: &mut Vec<u8>) -> Result<(), PXError> {
+        loop {
+            out.extend_from_slice(&self.pending[self.pending_position..]);
+            self.pending_position = self.pending.len();
+            if self.finished {
+                return Ok(());
+            };
+            self.refill()?;
+        }
+    }
+}
+
+impl<F: Read + Seek> Read for PxDecoder<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_px(buf).map_err(to_io_error)
+    }
+}
+
+/// an iterator over the decompressed bytes of a px file, returned by ``decompress_px_iter``.
+///
+/// The file is decompressed as the bytes are requested, so stopping early avoid decompressing the rest. An error is returned as an ``Err``
+/// item, after which the iteration stop.
+pub struct PxBytes<F: Read + Seek> {
+    decoder: PxDecoder<F>,
+}
+
+impl<F: Read + Seek> PxBytes<F> {
+    pub fn get_ref(&self) -> &PxDecoder<F> {
+        &self.decoder
+    }
+}
+
+impl<F: Read + Seek> Iterator for PxBytes<F> {
+    type Item = Result<u8, PXError>;
+
+    fn next(&mut self) -> Option<Result<u8, PXError>> {
+        self.decoder.next_byte().transpose()
+    }
+}
+
+/// decompress a pkdpx or at4px file lazily, returning an iterator over its decompressed bytes. The header is read immediately.
+pub fn decompress_px_iter<F: Read + Seek>(file: F) -> Result<PxBytes<F>, PXError> {
+    Ok(PxBytes {
+        decoder: PxDecoder::new(file)?,
+    })
+}
+
+/// wrap a ``PxDecoder``, keeping track of how much was consumed and produced. Useful to instrument a pipeline without changing it.
+pub struct CountingPxDecoder<F: Read + Seek> {
+    inner: PxDecoder<F>,
+    output_produced: u64,
+}
+
+impl<F: Read + Seek> CountingPxDecoder<F> {
+    pub fn new(inner: PxDecoder<F>) -> CountingPxDecoder<F> {
+        CountingPxDecoder {
+            inner,
+            output_produced: 0,
+        }
+    }
+
+    /// the number of byte of the px file read until now, header included
+    pub fn input_consumed(&self) -> u64 {
+        self.inner.input_position()
+    }
+
+    /// the number of decompressed byte returned until now
+    pub fn output_produced(&self) -> u64 {
+        self.output_produced
+    }
+
+    pub fn get_ref(&self) -> &PxDecoder<F> {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> PxDecoder<F> {
+        self.inner
+    }
+}
+
+impl<F: Read + Seek> Read for CountingPxDecoder<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let readed = self.inner.read(buf)?;
+        self.output_produced += readed as u64;
+        Ok(readed)
+    }
+}
+
+/// decompress only the bytes in ``range`` of a px file.
+///
+/// The decompression stop once ``range.end`` is reached, and the bytes before ``range.start`` are discarded as they are produced
+/// (they still need to be decompressed, as back-references may copy them). As the end of the file may not be reached, its container
+/// length isn't always checked. If the file is shorter than ``range.end``, only the bytes up to its end are returned.
+pub fn decompress_px_range<F: Read + Seek>(
+    file: F,
+    range: Range<usize>,
+) -> Result<Vec<u8>, PXError> {
+    let mut decoder = PxDecoder::new(file)?;
+    // the range may go far past the end of the file
+    let mut result = Vec::with_capacity(range.len().min(decoder.decompressed_length() as usize));
+    let mut buffer = [0; 4096];
+    let mut position = 0;
+    while position < range.end {
+        let to_read = (range.end - position).min(buffer.len());
+        let readed = decoder.read_px(&mut buffer[..to_read])?;
+        if readed == 0 {
+            break;
+        };
+        if position + readed > range.start {
+            result.extend_from_slice(&buffer[range.start.saturating_sub(position)..readed]);
+        };
+        position += readed;
+    }
+    Ok(result)
+}
+
+/// the number of decompressed byte between two calls to the callback of ``decompress_px_with_progress``
+const PROGRESS_INTERVAL: usize = 0x1000;
+
+/// decompress a pkdpx or at4px file, like ``decompress_px``, calling ``callback`` with the number of byte decompressed until now and
+/// the decompressed length indicated in the header.
+///
+/// It is called every 4096 decompressed bytes, and once more when the decompression end (with the final size).
+pub fn decompress_px_with_progress<F: Read + Seek, C: FnMut(usize, usize)>(
+    file: F,
+    mut callback: C,
+) -> Result<Vec<u8>, PXError> {
+    let mut decoder = PxDecoder::new(file)?;
+    let expected_total = decoder.header().decompressed_length as usize;
+    let mut result = output_buffer(decoder.header(), &DecodeOptions::default());
+    let mut buffer = [0; PROGRESS_INTERVAL];
+    loop {
+        let readed = decoder.read_px(&mut buffer)?;
+        if readed == 0 {
+            break;
+        };
+        result.extend_from_slice(&buffer[..readed]);
+        if result.len() / PROGRESS_INTERVAL != (result.len() - readed) / PROGRESS_INTERVAL {
+            callback(result.len(), expected_total);
+        };
+    }
+    callback(result.len(), expected_total);
+    Ok(result)
+}
+
+/// decompress a pkdpx or at4px file, like ``decompress_px``, writing the decompressed data to ``dest`` starting at the index ``at``. Return
+/// the number of byte written.
+///
+/// The bytes already in ``dest`` at these positions are overwritten, and it is extended if the decompressed data go past its end
+/// (if ``at`` is past its end, the gap is filled with 0). Back-references only copy from the decompressed data, so what is before ``at``
+/// doesn't matter. On error, ``dest`` may have been partially written.
+pub fn decompress_px_at<F: Read + Seek>(
+    file: F,
+    dest: &mut Vec<u8>,
+    at: usize,
+) -> Result<usize, PXError> {
+    let mut decoder = PxDecoder::new(file)?;
+    if dest.len() < at {
+        dest.resize(at, 0);
+    };
+    let mut position = at;
+    while position < dest.len() {
+        let readed = decoder.read_px(&mut dest[position..])?;
+        if readed == 0 {
+            return Ok(position - at);
+        };
+        position += readed;
+    }
+    reserve_output(
+        dest,
+        decoder.header(),
+        &DecodeOptions::default(),
+        position - at,
+    );
+    decoder.read_to_end_px(dest)?;
+    Ok(dest.len() - at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress_px_slice;
+    use std::io::Cursor;
+
+    fn sample() -> Vec<u8> {
+        (0..3000u32).map(|i| (i * 7 % 31) as u8).collect()
+    }
+
+    #[test]
+    fn counts_after_a_full_read() {
+        let data = sample();
+        let compressed = compress_px_slice(&data).unwrap();
+        let container_length = u16::from_le_bytes([compressed[5], compressed[6]]) as u64;
+        let mut decoder = CountingPxDecoder::new(PxDecoder::new(Cursor::new(&compressed)).unwrap());
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(decoder.input_consumed(), container_length);
+        assert_eq!(decoder.output_produced(), data.len() as u64);
+    }
+
+    #[test]
+    fn range_until_the_end() {
+        let data = sample();
+        let compressed = compress_px_slice(&data).unwrap();
+        assert_eq!(
+            decompress_px_range(Cursor::new(&compressed), 100..usize::MAX).unwrap(),
+            &data[100..]
+        );
+    }
+
+    #[test]
+    fn at_the_end_of_a_longer_buffer() {
+        let data = sample();
+        let compressed = compress_px_slice(&data).unwrap();
+        let mut dest = vec![0xAA; 10 + data.len() - 100];
+        assert_eq!(
+            decompress_px_at(Cursor::new(&compressed), &mut dest, 10).unwrap(),
+            data.len()
+        );
+        assert_eq!(&dest[..10], &[0xAA; 10]);
+        assert_eq!(&dest[10..], &data[..]);
+    }
+
+    #[test]
+    fn at_a_position_past_the_end() {
+        let data = sample();
+        let compressed = compress_px_slice(&data).unwrap();
+        let mut dest = vec![0xAA; 5];
+        assert_eq!(
+            decompress_px_at(Cursor::new(&compressed), &mut dest, 8).unwrap(),
+            data.len()
+        );
+        assert_eq!(&dest[..8], &[0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0, 0, 0]);
+        assert_eq!(&dest[8..], &data[..]);
+    }
+}
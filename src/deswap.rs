@@ -0,0 +1,66 @@
+use crate::{decompress_px, PXError, AT4PX_MAGIC, PKDPX_MAGIC};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// a way the bytes of a dumped file may have been reordered
+#[derive(Debug, Clone, Copy)]
+enum ByteSwap {
+    /// every 16-bit word have its two bytes swapped (``AB CD`` -> ``BA DC``)
+    Swap16,
+    /// every 32-bit word have its four bytes reversed (``ABCD`` -> ``DCBA``)
+    Swap32,
+}
+
+impl ByteSwap {
+    fn word_size(self) -> usize {
+        match self {
+            Self::Swap16 => 2,
+            Self::Swap32 => 4,
+        }
+    }
+
+    /// undo the swap. Swapping is its own inverse. A trailing incomplete word is left as is.
+    fn unswap(self, data: &mut [u8]) {
+        for word in data.chunks_exact_mut(self.word_size()) {
+            word.reverse();
+        }
+    }
+}
+
+fn has_px_magic(data: &[u8]) -> bool {
+    data.starts_with(&PKDPX_MAGIC) || data.starts_with(&AT4PX_MAGIC)
+}
+
+/// decompress a px file that may come from a dump where the bytes were swapped.
+///
+/// The swap is detected with the magic at the start of the file. The handled patterns are:
+/// - no swap at all (the file is decompressed as is)
+/// - 16-bit words with their two bytes swapped (``PKDPX`` appear as ``KPPD`` followed by the low byte of the container length and ``X``)
+/// - 32-bit words with their byte order reversed (``PKDPX`` appear as ``PDKP``, followed by ``X`` at the end of the second word)
+///
+/// Once detected, the whole file is un-swapped in memory before being decompressed. If the file end with an incomplete word, its bytes are kept as is.
+/// If no pattern produce a valid magic, ``PXError::InvalidHeaderMagic`` is returned with the first five bytes of the file.
+pub fn try_deswap_and_decompress<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    if has_px_magic(&data) {
+        return decompress_px(Cursor::new(data));
+    };
+
+    for swap in &[ByteSwap::Swap16, ByteSwap::Swap32] {
+        // 8 bytes is enought to contain the magic for every word size
+        let mut prefix = data[..data.len().min(8)].to_vec();
+        swap.unswap(&mut prefix);
+        if has_px_magic(&prefix) {
+            debug!("the px file is byte-swapped ({:?})", swap);
+            swap.unswap(&mut data);
+            return decompress_px(Cursor::new(data));
+        };
+    }
+
+    let mut header_5 = [0; 5];
+    let available = data.len().min(5);
+    header_5[..available].copy_from_slice(&data[..available]);
+    Err(PXError::InvalidHeaderMagic(header_5))
+}
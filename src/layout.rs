@@ -0,0 +1,147 @@
+#[cfg(feature = "std")]
+use crate::{check_header_fits, decompress_px_raw, DecodeOptions};
+use crate::{PXError, PxFormat, PxHeader, AT4PX_MAGIC, PKDPX_MAGIC};
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom};
+
+/// the position of the fields in the header of a px file. The standard headers are available as ``PKDPX``, ``AT4PX`` and
+/// ``AT4PX_32BIT``, but some modded containers rearrange them.
+///
+/// Every offset is in byte from the start of the file. The magic (5 bytes), the container length (2 bytes) and the control flags (9 bytes)
+/// have a fixed size, and the decompressed length is ``decompressed_length_size`` bytes long (2 or 4). The numbers are little endian.
+/// The header end with the last of these fields, and the compressed data start just after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PxLayoutDescriptor {
+    pub magic_offset: usize,
+    pub container_length_offset: usize,
+    pub control_flags_offset: usize,
+    pub decompressed_length_offset: usize,
+    pub decompressed_length_size: usize,
+}
+
+impl PxLayoutDescriptor {
+    /// the header of PKDPX files (``PKDPX_HEADER_LEN`` bytes)
+    pub const PKDPX: PxLayoutDescriptor = PxLayoutDescriptor {
+        magic_offset: 0,
+        container_length_offset: 5,
+        control_flags_offset: 7,
+        decompressed_length_offset: 16,
+        decompressed_length_size: 4,
+    };
+
+    /// the header of AT4PX files (``AT4PX_HEADER_LEN`` bytes)
+    pub const AT4PX: PxLayoutDescriptor = PxLayoutDescriptor {
+        decompressed_length_size: 2,
+        ..Self::PKDPX
+    };
+
+    /// the header of AT4PX files with a 32 bits decompressed length, like PKDPX (see ``DecodeOptions::at4px_32bit_length``)
+    pub const AT4PX_32BIT: PxLayoutDescriptor = Self::PKDPX;
+
+    fn fields(&self) -> [(usize, usize); 4] {
+        [
+            (self.magic_offset, 5),
+            (self.container_length_offset, 2),
+            (self.control_flags_offset, 9),
+            (
+                self.decompressed_length_offset,
+                self.decompressed_length_size,
+            ),
+        ]
+    }
+
+    /// the size of the header, up to the end of its last field
+    pub fn header_length(&self) -> usize {
+        self.fields()
+            .iter()
+            .map(|(offset, size)| offset + size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// check that the decompressed length has a supported size and that no field overlap another
+    pub fn validate(&self) -> Result<(), PXError> {
+        if self.decompressed_length_size != 2 && self.decompressed_length_size != 4 {
+            return Err(PXError::InvalidLayout(
+                "the decompressed length should be 2 or 4 bytes long",
+            ));
+        };
+        let mut fields = self.fields();
+        fields.sort_unstable();
+        for pair in fields.windows(2) {
+            if pair[0].0 + pair[0].1 > pair[1].0 {
+                return Err(PXError::InvalidLayout("two fields of the header overlap"));
+            };
+        }
+        Ok(())
+    }
+}
+
+impl PxHeader {
+    /// read the header at the start of the file, with its fields at the position given by ``layout``. The cursor is left just after the header.
+    #[cfg(feature = "std")]
+    pub fn parse_with_layout<F: Read + Seek>(
+        file: &mut F,
+        layout: &PxLayoutDescriptor,
+    ) -> Result<PxHeader, PXError> {
+        layout.validate()?;
+        check_header_fits(file, layout.header_length())?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut header = vec![0; layout.header_length()];
+        file.read_exact(&mut header)?;
+        Self::from_bytes(&header, layout)
+    }
+
+    /// read the fields of a header of exactly ``layout.header_length()`` bytes
+    pub(crate) fn from_bytes(
+        header: &[u8],
+        layout: &PxLayoutDescriptor,
+    ) -> Result<PxHeader, PXError> {
+        let mut header_5 = [0; 5];
+        header_5.copy_from_slice(&header[layout.magic_offset..layout.magic_offset + 5]);
+        let format = if header_5 == PKDPX_MAGIC {
+            PxFormat::Pkdpx
+        } else if header_5 == AT4PX_MAGIC {
+            PxFormat::At4px
+        } else {
+            return Err(PXError::InvalidHeaderMagic(header_5));
+        };
+
+        let mut control_flags = [0; 9];
+        control_flags
+            .copy_from_slice(&header[layout.control_flags_offset..layout.control_flags_offset + 9]);
+
+        let read_le = |offset: usize, size: usize| {
+            header[offset..offset + size]
+                .iter()
+                .rev()
+                .fold(0u32, |value, byte| value << 8 | *byte as u32)
+        };
+        let container_length = read_le(layout.container_length_offset, 2) as u16;
+        let decompressed_length = read_le(
+            layout.decompressed_length_offset,
+            layout.decompressed_length_size,
+        );
+
+        Ok(PxHeader {
+            format,
+            container_length,
+            control_flags,
+            decompressed_length,
+            header_length: header.len() as u64,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+/// decompress a px file whose header follow ``layout`` rather than the standard one. The magic should still be PKDPX or AT4PX.
+pub fn decompress_px_with_layout<F: Read + Seek>(
+    mut file: F,
+    layout: &PxLayoutDescriptor,
+) -> Result<Vec<u8>, PXError> {
+    let header = PxHeader::parse_with_layout(&mut file, layout)?;
+    let mut result = Vec::new();
+    decompress_px_raw(file, &header, &DecodeOptions::default(), &mut result)?;
+    Ok(result)
+}
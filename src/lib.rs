@@ -1,237 +1,1125 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #[macro_use]
 extern crate log;
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
 use io_partition::Partition;
-use std::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
 use std::io;
-use std::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Write};
 
+mod arith;
+
+#[cfg(feature = "std")]
+mod codec;
+#[cfg(feature = "std")]
+pub use codec::{At4pxCodec, PkdpxCodec, PxCodec};
+
+#[cfg(feature = "std")]
+mod compress;
+#[cfg(feature = "std")]
+pub use compress::{
+    canonicalize_px, canonicalize_px_to, choose_control_flags, compare_levels, compress_at4px,
+    compress_px, compress_px_banked, compress_px_from_read, compress_px_if_smaller,
+    compress_px_level, compress_px_slice, compress_px_stats, compress_px_with_finder,
+    compress_px_with_flags, compress_px_with_options, verify_roundtrip, CompressOptions,
+    CompressOutcome, CompressStats, CompressionLevel, PxBanks,
+};
+
+#[cfg(feature = "std")]
+mod decoder;
+#[cfg(feature = "std")]
+pub use decoder::{
+    decompress_px_at, decompress_px_iter, decompress_px_range, decompress_px_with_progress,
+    CountingPxDecoder, PxBytes, PxDecoder,
+};
+
+mod layout;
+#[cfg(feature = "std")]
+pub use layout::decompress_px_with_layout;
+pub use layout::PxLayoutDescriptor;
+
+#[cfg(feature = "std")]
+mod producer_tag;
+#[cfg(feature = "std")]
+pub use producer_tag::{read_producer_tag, MAX_PRODUCER_TAG_LEN};
+
+#[cfg(feature = "std")]
+mod match_finder;
+#[cfg(feature = "std")]
+pub use match_finder::{BruteForceMatchFinder, HashChainMatchFinder, MatchFinder};
+
+#[cfg(feature = "std")]
+mod deswap;
+#[cfg(feature = "std")]
+pub use deswap::try_deswap_and_decompress;
+
+#[cfg(feature = "std")]
+mod analysis;
+#[cfg(feature = "std")]
+pub use analysis::{aggregate_control_flags, px_container_eq};
+
+#[cfg(feature = "std")]
+mod pmd_compression;
+#[cfg(feature = "std")]
+pub use pmd_compression::{decompress_any, detect_compression, PmdCompression};
+
+#[cfg(feature = "std")]
+mod sir0;
+#[cfg(feature = "std")]
+pub use sir0::slice_sir0_objects;
+
+#[cfg(feature = "std")]
+mod trace_json;
+#[cfg(feature = "std")]
+pub use trace_json::decompress_px_trace_json;
+
+#[cfg(feature = "std")]
+mod validate;
+#[cfg(feature = "std")]
+pub use validate::validate_backrefs;
+
+/// the bit ``id`` of ``byte``, 0 being the most significant one. ``None`` if ``id`` is 8 or more.
 fn get_bit(byte: u8, id: usize) -> Option<bool> {
     if id < 8 {
-        Some((byte >> (7 - id) << 7) >= 1)
+        Some((byte >> (7 - id)) & 1 == 1)
     } else {
         None
     }
 }
 
+/// the 8 bits of a command byte, the most significant first
+fn command_bits(byte: u8) -> [bool; 8] {
+    let mut bits = [false; 8];
+    for (id, bit) in bits.iter_mut().enumerate() {
+        *bit = get_bit(byte, id).unwrap();
+    }
+    bits
+}
+
 #[derive(Debug)]
 pub enum PXError {
+    #[cfg(feature = "std")]
     IOError(io::Error),
     InvalidHeaderMagic([u8; 5]),
     InvalidDecompressedLength,
     FileToCompressTooLong(usize),
+    InconsistentLengths {
+        container_length: u16,
+        decompressed_length: u32,
+    },
+    #[cfg(feature = "std")]
+    UnsupportedCompression(PmdCompression),
+    InvalidSir0(&'static str),
+    InvalidBackReference {
+        offset: i64,
+        len: usize,
+    },
+    InvalidControlPattern {
+        index: usize,
+        nb_low: u8,
+    },
+    InvalidBankSize,
+    InvalidLayout(&'static str),
+    InvalidProducerTag(&'static str),
+    TooLongForAt4px(usize),
+    OutputTooLarge(usize),
+    TruncatedBody,
+    TruncatedHeader {
+        needed: usize,
+        got: usize,
+    },
+    AmbiguousControlFlags {
+        flag: u8,
+        first_index: usize,
+        second_index: usize,
+    },
+    ImplausibleDecompressedLength {
+        container_length: u16,
+        decompressed_length: u32,
+    },
 }
 
 impl fmt::Display for PXError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::IOError(_) => write!(f, "An IO error happened"),
+            #[cfg(feature = "std")]
+            Self::IOError(err) => write!(f, "An IO error happened: {}", err),
             Self::InvalidHeaderMagic(value) => write!(f, "The header is invalid. It should either be PKDPX or AT4PX. The actual value of this header (in base 10) is {:?}", value),
             Self::InvalidDecompressedLength => write!(f, "The decompressed lenght doesn't correspond to what is indicated in the file"),
-            Self::FileToCompressTooLong(lenght) => write!(f, "The file to compress is too long (real size: {}, max size: 256*256)", lenght)
+            Self::FileToCompressTooLong(lenght) => write!(f, "The file to compress is too long (real size: {}, max size: 256*256)", lenght),
+            Self::InconsistentLengths { container_length, decompressed_length } => write!(f, "The container length ({}) and the decompressed length ({}) indicated in the header can't both be true", container_length, decompressed_length),
+            #[cfg(feature = "std")]
+            Self::UnsupportedCompression(compression) => write!(f, "The compression {:?} isn't supported", compression),
+            Self::InvalidSir0(reason) => write!(f, "The SIR0 file is invalid: {}", reason),
+            Self::InvalidBackReference { offset, len } => write!(f, "A back-reference copy {} bytes from the position {} of the output, which is before its start", len, offset),
+            Self::InvalidControlPattern { index, nb_low } => write!(f, "The control flag {} produce a nibble out of range with the value {}", index, nb_low),
+            Self::InvalidBankSize => write!(f, "The bank size should be at least one byte"),
+            Self::InvalidLayout(reason) => write!(f, "The header layout is invalid: {}", reason),
+            Self::InvalidProducerTag(reason) => write!(f, "The producer tag can't be stored: {}", reason),
+            Self::TooLongForAt4px(lenght) => write!(f, "The file to compress is too long to be stored in an AT4PX file (real size: {}, max size: 65535)", lenght),
+            Self::OutputTooLarge(max_output) => write!(f, "The decompressed file would be bigger than the limit of {} bytes", max_output),
+            Self::TruncatedBody => write!(f, "The file end before the decompressed length is reached"),
+            Self::TruncatedHeader { needed, got } => write!(f, "The file is too short to contain its header ({} bytes are needed, but there are only {})", needed, got),
+            Self::AmbiguousControlFlags { flag, first_index, second_index } => write!(f, "The control flags {} and {} are both {}, so the second one can never be used", first_index, second_index, flag),
+            Self::ImplausibleDecompressedLength { container_length, decompressed_length } => write!(f, "The decompressed length ({}) indicated in the header is more than what a container of {} bytes can contain (every byte of compressed data produce at most 9 bytes)", decompressed_length, container_length)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for PXError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IOError(err) => Some(err),
+            _ => None,
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<io::Error> for PXError {
     fn from(err: io::Error) -> Self {
         Self::IOError(err)
     }
 }
 
-#[derive(Debug)]
-struct ControlFlags {
-    value: [u8; 9],
+/// describe the two bytes produced by a control flag, from the low nibble of the command byte.
+///
+/// Every of the four output nibbles is first set to ``nb_low + base_adjust``, then ``nibble_adjust`` is added to the nibble at index ``nibble`` (0 being the high nibble of the first byte).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlOp {
+    pub base_adjust: i8,
+    pub nibble: Option<usize>,
+    pub nibble_adjust: i8,
 }
 
-impl ControlFlags {
-    fn new(value: [u8; 9]) -> ControlFlags {
-        ControlFlags { value }
+impl ControlOp {
+    const fn new(base_adjust: i8, nibble: Option<usize>, nibble_adjust: i8) -> ControlOp {
+        ControlOp {
+            base_adjust,
+            nibble,
+            nibble_adjust,
+        }
     }
 
-    fn find(&self, nb_high: u8) -> Option<usize> {
-        for v in 0..self.value.len() {
-            if self.value[v] == nb_high {
-                return Some(v);
-            }
-        }
-        None
+    /// return the two bytes produced by this operation for the given low nibble, or ``None`` if a nibble get out of the 0-15 range
+    /// (the decompressor then return ``PXError::InvalidControlPattern``, for example for the sixth flag with a ``nb_low`` of 0).
+    /// ``None`` is also returned if ``nb_low`` isn't a nibble (more than 15), or if ``nibble`` isn't between 0 and 3.
+    pub fn apply(&self, nb_low: u8) -> Option<(u8, u8)> {
+        if nb_low > 0xF {
+            return None;
+        };
+        let base = (nb_low as i8).checked_add(self.base_adjust)?;
+        let mut nibbles = [base; 4];
+        if let Some(nibble) = self.nibble {
+            let adjusted = nibbles.get_mut(nibble)?;
+            *adjusted = adjusted.checked_add(self.nibble_adjust)?;
+        };
+        if nibbles.iter().any(|nibble| !(0..16).contains(nibble)) {
+            return None;
+        };
+        Some((
+            ((nibbles[0] << 4) | nibbles[1]) as u8,
+            ((nibbles[2] << 4) | nibbles[3]) as u8,
+        ))
     }
 }
 
-fn px_read_u16<T: Read>(file: &mut T) -> Result<u16, PXError> {
-    let mut buf = [0; 2];
-    file.read_exact(&mut buf)?;
-    Ok(u16::from_le_bytes(buf))
+/// the operation associated with each of the nine control flags, in the order they appear in the header
+///
+/// The first one repeat ``nb_low`` in the four nibbles (``0x00 0x00`` for 0 to ``0xFF 0xFF`` for 15). Unlike the others, it is valid for
+/// every value of ``nb_low``.
+pub const PX_CONTROL_OPS: [ControlOp; 9] = [
+    ControlOp::new(0, None, 0),
+    ControlOp::new(1, Some(0), -1),
+    ControlOp::new(0, Some(1), -1),
+    ControlOp::new(0, Some(2), -1),
+    ControlOp::new(0, Some(3), -1),
+    ControlOp::new(-1, Some(0), 1),
+    ControlOp::new(0, Some(1), 1),
+    ControlOp::new(0, Some(2), 1),
+    ControlOp::new(0, Some(3), 1),
+];
+
+/// the nine control flags stored in the header of a px file. Flags of 16 or more never match a nibble, and so are never used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlFlags {
+    value: [u8; 9],
+    /// the result of ``find`` for every possible value
+    lookup: [Option<u8>; 256],
 }
 
-fn px_read_u32<T: Read>(file: &mut T) -> Result<u32, PXError> {
-    let mut buf = [0; 4];
-    file.read_exact(&mut buf)?;
-    Ok(u32::from_le_bytes(buf))
+impl ControlFlags {
+    pub fn new(value: [u8; 9]) -> ControlFlags {
+        let mut lookup = [None; 256];
+        // in reverse order, so the first matching flag is the one kept
+        for (index, flag) in value.iter().enumerate().rev() {
+            lookup[*flag as usize] = Some(index as u8);
+        }
+        ControlFlags { value, lookup }
+    }
+
+    /// the flags, as stored in the header
+    pub fn value(&self) -> [u8; 9] {
+        self.value
+    }
+
+    /// return the index of the flag (and of the operation in ``PX_CONTROL_OPS``) used by a command byte whose high nibble is ``nb_high``,
+    /// or ``None`` if this command byte start a back-reference. If several flags are equal, the first one is used.
+    pub fn find(&self, nb_high: u8) -> Option<usize> {
+        self.lookup[nb_high as usize].map(|index| index as usize)
+    }
+
+    /// check that no two flags that can match a nibble (below 16) are equal. Otherwise, only the first one is used by ``find``, while
+    /// the compressor that made the file may have meant the other one: return ``PXError::AmbiguousControlFlags``.
+    ///
+    /// The decompressor doesn't check it, as many files (including the one made by this crate) have duplicated flags they don't use.
+    pub fn validate(&self) -> Result<(), PXError> {
+        for (second_index, flag) in self.value.iter().enumerate() {
+            if *flag >= 16 {
+                continue;
+            };
+            if let Some(first_index) = self.find(*flag) {
+                if first_index != second_index {
+                    return Err(PXError::AmbiguousControlFlags {
+                        flag: *flag,
+                        first_index,
+                        second_index,
+                    });
+                };
+            };
+        }
+        Ok(())
+    }
 }
 
+#[cfg(feature = "std")]
 fn px_read_u8<T: Read>(file: &mut T) -> Result<u8, PXError> {
     let mut buf = [0];
     file.read_exact(&mut buf)?;
     Ok(buf[0])
 }
 
+#[cfg(feature = "std")]
+/// return ``PXError::TruncatedHeader`` if ``file`` is shorter than the ``needed`` bytes of its header
+fn check_header_fits<F: Seek>(file: &mut F, needed: usize) -> Result<(), PXError> {
+    let got = file.seek(SeekFrom::End(0))?;
+    if got < needed as u64 {
+        return Err(PXError::TruncatedHeader {
+            needed,
+            got: got as usize,
+        });
+    };
+    Ok(())
+}
+
+/// where the bytes of a px body are read from
+trait ByteSource {
+    fn read_byte(&mut self) -> Result<u8, PXError>;
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> ByteSource for R {
+    fn read_byte(&mut self) -> Result<u8, PXError> {
+        px_read_u8(self)
+    }
+}
+
+/// read the bytes of a slice, without ``std::io``
+#[cfg(not(feature = "std"))]
+struct SliceSource<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+#[cfg(not(feature = "std"))]
+impl ByteSource for SliceSource<'_> {
+    fn read_byte(&mut self) -> Result<u8, PXError> {
+        let byte = *self.data.get(self.position).ok_or(PXError::TruncatedBody)?;
+        self.position += 1;
+        Ok(byte)
+    }
+}
+
+/// the magic at the start of PKDPX files
+pub const PKDPX_MAGIC: [u8; 5] = *b"PKDPX";
+/// the magic at the start of AT4PX files
+pub const AT4PX_MAGIC: [u8; 5] = *b"AT4PX";
+/// the size of the header of PKDPX files, in byte
+pub const PKDPX_HEADER_LEN: u64 = 20;
+/// the size of the header of AT4PX files, in byte (their decompressed length is 2 bytes shorter)
+pub const AT4PX_HEADER_LEN: u64 = 18;
+
+/// the two variant of px compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PxFormat {
+    Pkdpx,
+    At4px,
+}
+
+/// the information stored in the header of a px file. It can be read without decompressing the file with ``PxHeader::parse``.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PxHeader {
+    pub format: PxFormat,
+    /// the length of the header and the compressed data, without the padding
+    pub container_length: u16,
+    pub control_flags: [u8; 9],
+    pub decompressed_length: u32,
+    /// the size of the header, in byte (20 for PKDPX, 18 for AT4PX)
+    pub header_length: u64,
+}
+
+/// the parameters of the decompressor
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// read the decompressed length of AT4PX files on 32 bits, like PKDPX, making the header 20 bytes long.
+    /// Some tools produce such hybrid files.
+    pub at4px_32bit_length: bool,
+    /// the last command may produce more byte than the decompressed length indicated in the header (a pattern or
+    /// back-reference started just before the end). The header indicate the size of the original data, and the official
+    /// compressor never overshoot it, so these bytes aren't part of the file. If true, they are dropped. They are kept by default,
+    /// like previous versions of this crate.
+    pub truncate_overshoot: bool,
+    /// the maximum number of byte to output. If the decompressed length indicated in the header, or the output, is bigger,
+    /// ``PXError::OutputTooLarge`` is returned before writing it. Without limit, the output is still never more than 17 bytes longer
+    /// than the decompressed length (and this length is checked to be plausible for the size of the compressed data).
+    pub max_output: Option<usize>,
+    /// don't check that the compressed data end where the container length indicate (see ``PXError::InvalidDecompressedLength``).
+    /// Some tools write a slightly wrong container length in files that otherwise decompress correctly. The container length is still
+    /// used to check that the decompressed length is plausible.
+    pub ignore_container_length: bool,
+}
+
+impl DecodeOptions {
+    fn check_output_limit(&self, output_length: usize) -> Result<(), PXError> {
+        match self.max_output {
+            Some(max_output) if output_length > max_output => {
+                Err(PXError::OutputTooLarge(max_output))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl PxHeader {
+    /// read the header at the start of the file. The cursor is left just after the header.
+    #[cfg(feature = "std")]
+    pub fn parse<F: Read + Seek>(file: &mut F) -> Result<PxHeader, PXError> {
+        Self::parse_with_options(file, &DecodeOptions::default())
+    }
+
+    /// read the header at the start of the file, like ``parse``, with the given options.
+    #[cfg(feature = "std")]
+    pub fn parse_with_options<F: Read + Seek>(
+        file: &mut F,
+        options: &DecodeOptions,
+    ) -> Result<PxHeader, PXError> {
+        check_header_fits(file, 5)?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut header_5 = [0; 5];
+        file.read_exact(&mut header_5)?;
+
+        let layout = Self::layout_for_magic(header_5, options)?;
+        Self::parse_with_layout(file, &layout)
+    }
+
+    /// read the header at the start of ``data``, like ``parse``
+    pub fn parse_slice(data: &[u8]) -> Result<PxHeader, PXError> {
+        let mut header_5 = [0; 5];
+        header_5.copy_from_slice(data.get(..5).ok_or(PXError::TruncatedHeader {
+            needed: 5,
+            got: data.len(),
+        })?);
+        let layout = Self::layout_for_magic(header_5, &DecodeOptions::default())?;
+        let header_length = layout.header_length();
+        let header = data.get(..header_length).ok_or(PXError::TruncatedHeader {
+            needed: header_length,
+            got: data.len(),
+        })?;
+        Self::from_bytes(header, &layout)
+    }
+
+    /// the layout of the header of the files starting with ``magic``
+    fn layout_for_magic(
+        magic: [u8; 5],
+        options: &DecodeOptions,
+    ) -> Result<PxLayoutDescriptor, PXError> {
+        if magic == PKDPX_MAGIC {
+            Ok(PxLayoutDescriptor::PKDPX)
+        } else if magic == AT4PX_MAGIC {
+            if options.at4px_32bit_length {
+                Ok(PxLayoutDescriptor::AT4PX_32BIT)
+            } else {
+                Ok(PxLayoutDescriptor::AT4PX)
+            }
+        } else {
+            Err(PXError::InvalidHeaderMagic(magic))
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+/// read the decompressed length stored in the header of a pkdpx or at4px file, without decompressing it or reading past the header
+pub fn decompressed_length<F: Read + Seek>(file: &mut F) -> Result<u32, PXError> {
+    Ok(PxHeader::parse(file)?.decompressed_length)
+}
+
+#[cfg(feature = "std")]
 /// decompress a pkdpx or at4px file. It take as input a Bytes buffer, and return a decompressed buffer (or an error)
 ///
 /// If atomatically determine if it is a pkdpx or an at4px based on the header
 /// If the file isn't the good lenght, it check if what is missing is a padding of a sir0. If it isn't, it return an error.
+/// A corrupted body also return an error rather than panicking (for example, ``PXError::InvalidBackReference`` for a back-reference that
+/// copy from before the start of the output), so untrusted files can be decompressed.
+pub fn decompress_px<F: Read + Seek>(file: F) -> Result<Vec<u8>, PXError> {
+    debug!("decompressing a px-compressed file file");
+    let mut decoder = PxDecoder::new(file)?;
+    let mut result = output_buffer(decoder.header(), &DecodeOptions::default());
+    decoder.read_to_end_px(&mut result)?;
+    Ok(result)
+}
 
-pub fn decompress_px<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError> {
+#[cfg(feature = "std")]
+/// decompress a pkdpx or at4px file, like ``decompress_px``, into ``out``. It is cleared first, but its allocation is reused (and grown if
+/// needed), so a single buffer can be used to decompress many files.
+///
+/// On error, ``out`` contain what was decompressed before the error.
+pub fn decompress_px_into<F: Read + Seek>(file: F, out: &mut Vec<u8>) -> Result<(), PXError> {
     debug!("decompressing a px-compressed file file");
-    file.seek(SeekFrom::Start(0))?;
-    let mut header_5 = [0; 5];
-    file.read_exact(&mut header_5)?;
+    let mut decoder = PxDecoder::new(file)?;
+    out.clear();
+    reserve_output(out, decoder.header(), &DecodeOptions::default(), 0);
+    decoder.read_to_end_px(out)
+}
 
-    let container_lenght = px_read_u16(&mut file)?;
-
-    let mut control_flags_buffer = [0; 9];
-    file.read_exact(&mut control_flags_buffer)?;
-    let control_flags = ControlFlags::new(control_flags_buffer);
-
-    if &header_5 == b"PKDPX" {
-        let decompressed_lenght = px_read_u32(&mut file)?;
-        Ok(decompress_px_raw(
-            file,
-            control_flags,
-            decompressed_lenght,
-            container_lenght,
-            20,
-        )?)
-    } else if &header_5 == b"AT4PX" {
-        let decompressed_lenght = px_read_u16(&mut file)? as u32;
-        Ok(decompress_px_raw(
-            file,
-            control_flags,
-            decompressed_lenght,
-            container_lenght,
-            18,
-        )?)
-    } else {
-        Err(PXError::InvalidHeaderMagic(header_5))
+/// create a ``Vec`` with enought capacity for the decompressed data of a file with this header (see ``reserve_output``)
+fn output_buffer(header: &PxHeader, options: &DecodeOptions) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    reserve_output(&mut buffer, header, options, 0);
+    buffer
+}
+
+/// reserve enought capacity in ``out`` for the decompressed data of a file with this header, minus the ``produced`` bytes already
+/// written. The decompressed length is only trusted if it is plausible for the size of the compressed data and below the output limit,
+/// so a bogus header can't cause a huge allocation.
+fn reserve_output(out: &mut Vec<u8>, header: &PxHeader, options: &DecodeOptions, produced: usize) {
+    let decompressed_length = header.decompressed_length as usize;
+    if produced >= decompressed_length {
+        return;
+    };
+    if check_lengths(
+        header.decompressed_length,
+        header.container_length,
+        header.header_length,
+    )
+    .is_err()
+        || options.check_output_limit(decompressed_length).is_err()
+    {
+        return;
+    };
+    // the last command may produce a bit more than the decompressed length
+    out.reserve(decompressed_length - produced + MAX_COMMAND_OUTPUT);
+}
+
+/// decompress a pkdpx or at4px file already in memory, like ``decompress_px``
+#[cfg(feature = "std")]
+pub fn decompress_px_slice(data: &[u8]) -> Result<Vec<u8>, PXError> {
+    decompress_px(Cursor::new(data))
+}
+
+/// decompress every file of ``inputs`` with ``decompress_px_slice``. One result is returned per input, in the same order, so a corrupted
+/// file doesn't prevent the others from being decompressed.
+///
+/// With the ``parallel`` feature, the files are decompressed on multiple threads.
+#[cfg(feature = "std")]
+pub fn decompress_px_batch(inputs: Vec<Vec<u8>>) -> Vec<Result<Vec<u8>, PXError>> {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        inputs
+            .par_iter()
+            .map(|input| decompress_px_slice(input))
+            .collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        inputs
+            .iter()
+            .map(|input| decompress_px_slice(input))
+            .collect()
     }
 }
 
-fn decompress_px_raw<T: Read + Seek>(
-    mut file: T,
-    control_flags: ControlFlags,
-    decompressed_lenght: u32,
-    container_lenght: u16,
-    header_lenght: u64,
+/// decompress a pkdpx or at4px file already in memory, like ``decompress_px``
+#[cfg(not(feature = "std"))]
+pub fn decompress_px_slice(data: &[u8]) -> Result<Vec<u8>, PXError> {
+    let header = PxHeader::parse_slice(data)?;
+    let options = DecodeOptions::default();
+    let mut body_decoder = BodyDecoder::new(&header, &options)?;
+    let mut result = output_buffer(&header, &options);
+    let mut body = SliceSource {
+        data,
+        position: header.header_length as usize,
+    };
+    while !body_decoder.is_finished() {
+        body_decoder.decode_command_byte(&mut body, &mut result, &mut |_, _, _| Ok(()))?;
+    }
+    Ok(result)
+}
+
+#[cfg(feature = "std")]
+/// decompress a pkdpx or at4px file, writing the decompressed data to ``out`` as it is produced. Return the number of byte written.
+///
+/// ``out`` doesn't need to be seekable: the last decompressed bytes that back-references copy from are kept in memory.
+/// On error, what was already decompressed may have been written to ``out``.
+pub fn decompress_px_to<F: Read + Seek, W: Write>(
+    mut file: F,
+    out: &mut W,
+) -> Result<u64, PXError> {
+    debug!("decompressing a px-compressed file file");
+    let options = DecodeOptions::default();
+    let header = PxHeader::parse_with_options(&mut file, &options)?;
+    Ok(decompress_px_raw(file, &header, &options, out)?.decompressed_length)
+}
+
+#[cfg(feature = "std")]
+/// decompress a pkdpx or at4px file, like ``decompress_px``, with the given options.
+pub fn decompress_px_with_options<F: Read + Seek>(
+    mut file: F,
+    options: &DecodeOptions,
 ) -> Result<Vec<u8>, PXError> {
-    let mut result = Vec::new();
-    let current_file_position = file.seek(SeekFrom::Current(0))?;
-    let current_file_len = file.seek(SeekFrom::End(0))?;
-    let mut raw_file = Partition::new(
+    let header = PxHeader::parse_with_options(&mut file, options)?;
+    let mut result = output_buffer(&header, options);
+    decompress_px_raw(file, &header, options, &mut result)?;
+    Ok(result)
+}
+
+#[cfg(feature = "std")]
+/// decompress a pkdpx or at4px file, like ``decompress_px``, but return ``PXError::OutputTooLarge`` rather than outputting more than
+/// ``max_output`` bytes.
+pub fn decompress_px_with_limit<F: Read + Seek>(
+    file: F,
+    max_output: usize,
+) -> Result<Vec<u8>, PXError> {
+    decompress_px_with_options(
         file,
-        current_file_position,
-        current_file_len - current_file_position,
+        &DecodeOptions {
+            max_output: Some(max_output),
+            ..Default::default()
+        },
     )
-    .unwrap();
+}
 
-    trace!("starting decompression ...");
-    'main: loop {
-        let mut bit_num = 0;
-        let byte_info = px_read_u8(&mut raw_file)?;
-        trace!("command byte: 0x{:x}", byte_info);
-        while bit_num < 8 {
-            let this_bit = get_bit(byte_info, bit_num).unwrap();
-            let this_byte = px_read_u8(&mut raw_file)?;
-
-            if this_bit {
-                trace!("bit is 1: pushing 0x{:2x}", this_byte);
-                result.push(this_byte);
-            } else {
-                let nb_high: u8 = this_byte >> 4;
-                let nb_low: u8 = this_byte << 4 >> 4;
-                match control_flags.find(nb_high) {
-                    Some(ctrlflagindex) => {
-                        let byte_to_add = match ctrlflagindex {
-                            0 => {
-                                let byte1 = (nb_low << 4) + nb_low;
-                                (byte1, byte1)
-                            }
-                            _ => {
-                                let mut nybbleval = nb_low;
-                                match ctrlflagindex {
-                                    1 => nybbleval += 1,
-                                    5 => nybbleval -= 1,
-                                    _ => (),
-                                };
-                                let mut nybbles = (nybbleval, nybbleval, nybbleval, nybbleval);
-                                match ctrlflagindex {
-                                    1 => nybbles.0 -= 1,
-                                    2 => nybbles.1 -= 1,
-                                    3 => nybbles.2 -= 1,
-                                    4 => nybbles.3 -= 1,
-                                    5 => nybbles.0 += 1,
-                                    6 => nybbles.1 += 1,
-                                    7 => nybbles.2 += 1,
-                                    8 => nybbles.3 += 1,
-                                    _ => panic!(),
-                                }
-                                ((nybbles.0 << 4) + nybbles.1, (nybbles.2 << 4) + nybbles.3)
-                            }
-                        };
-                        trace!("bit is 0: ctrlflagindex is {:x}, nb_high is {:x}, nb_low is {:x}, adding 0x{:2x}{:2x}", ctrlflagindex, nb_high, nb_low, byte_to_add.0, byte_to_add.1);
-                        result.push(byte_to_add.0);
-                        result.push(byte_to_add.1);
-                    }
-                    None => {
-                        let new_byte = px_read_u8(&mut raw_file)?;
-                        let offset_rel: i16 =
-                            -0x1000 + (((nb_low as i16) * 256) + (new_byte as i16));
-                        let offset = (offset_rel as i32) + (result.len() as i32);
-                        let lenght = (nb_high as i32) + 3;
-                        trace!("bit is 0: pushing from past, relative offset is {}, lenght is {} (nb_low:{}, nb_high:{}, new_byte:0x{:2x})", offset_rel, lenght, nb_low, nb_high, new_byte);
-                        // the old, good looking code
-                        /*result.seek(offset as u64);
-                        for c in result.read(lenght as u64)? {
-                            result.add_a_byte(c)?;
-                        }*/
-                        //TODO: check for panic
-                        for c in offset..(offset + lenght) {
-                            result.push(result[c as usize])
-                        }
-                    }
+/// a decompressed px file, with information about how it was stored
+#[derive(Debug, Clone)]
+pub struct DecompressedPx {
+    pub data: Vec<u8>,
+    /// the number of byte following the compressed data in the file. The official tools pad the file to a multiple of 16 bytes with 0xAA.
+    pub trailing_padding_len: usize,
+}
+
+#[cfg(feature = "std")]
+/// decompress a pkdpx or at4px file, like ``decompress_px``, but also return information on the storage of the file.
+pub fn decompress_px_detailed<F: Read + Seek>(mut file: F) -> Result<DecompressedPx, PXError> {
+    let options = DecodeOptions::default();
+    let header = PxHeader::parse_with_options(&mut file, &options)?;
+    let mut data = output_buffer(&header, &options);
+    let result = decompress_px_raw(file, &header, &options, &mut data)?;
+    Ok(DecompressedPx {
+        data,
+        trailing_padding_len: result.trailing_length as usize,
+    })
+}
+
+#[cfg(feature = "std")]
+/// decompress a px body stored separately from its header. The body should start at the beginning of ``body``.
+///
+/// ``header`` can be parsed from elsewhere with ``PxHeader::parse``, or built by hand. The container length it indicate still include the header length.
+pub fn decompress_px_split_header<B: Read + Seek>(
+    header: &PxHeader,
+    mut body: B,
+) -> Result<Vec<u8>, PXError> {
+    body.seek(SeekFrom::Start(0))?;
+    let options = DecodeOptions::default();
+    let mut result = output_buffer(header, &options);
+    decompress_px_raw(body, header, &options, &mut result)?;
+    Ok(result)
+}
+
+#[cfg(feature = "std")]
+/// collect the bytes written to it in a ``Vec``, after transforming them with ``map``
+struct MapWriter<M: FnMut(u8) -> u8> {
+    result: Vec<u8>,
+    map: M,
+}
+
+#[cfg(feature = "std")]
+impl<M: FnMut(u8) -> u8> Write for MapWriter<M> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let map = &mut self.map;
+        self.result.extend(buf.iter().map(|byte| map(*byte)));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+/// decompress a pkdpx or at4px file, applying ``map`` to every decompressed byte as they are produced.
+///
+/// back-references are resolved with the decompressed bytes, before ``map`` is applied, so the result is the same as mapping the output of ``decompress_px``.
+pub fn decompress_px_map<F: Read + Seek>(
+    file: F,
+    map: impl FnMut(u8) -> u8,
+) -> Result<Vec<u8>, PXError> {
+    let mut writer = MapWriter {
+        result: Vec::new(),
+        map,
+    };
+    decompress_px_to(file, &mut writer)?;
+    Ok(writer.result)
+}
+
+/// the size of the sliding window back-references can copy from (the offset is at least -0x1000)
+const WINDOW_SIZE: usize = 0x1000;
+/// the maximum number of byte a single command can produce (a back-reference of length 15 + 3)
+const MAX_COMMAND_OUTPUT: usize = 18;
+
+/// keep the last decompressed bytes, so back-references can be resolved without reading the output back
+struct Window {
+    buffer: [u8; WINDOW_SIZE],
+    /// total number of byte that were pushed
+    len: usize,
+}
+
+impl Window {
+    fn new() -> Window {
+        Window {
+            buffer: [0; WINDOW_SIZE],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.buffer[self.len % WINDOW_SIZE] = byte;
+        self.len += 1;
+    }
+
+    /// return the byte at the absolute position ``pos`` of the output. It should be in the last ``WINDOW_SIZE`` bytes.
+    fn get(&self, pos: usize) -> u8 {
+        self.buffer[pos % WINDOW_SIZE]
+    }
+}
+
+/// check that the body size indicated by the header could produce the indicated decompressed length.
+///
+/// Every body byte (other than the command bytes) produce between one and nine bytes, and the last command can go up to 17 bytes past
+/// the decompressed length, which bound the decompressed length from above and the body length from below.
+/// A decompressed length above this bound return ``PXError::ImplausibleDecompressedLength``, the other inconsistencies
+/// ``PXError::InconsistentLengths``.
+fn check_lengths(
+    decompressed_length: u32,
+    container_length: u16,
+    header_length: u64,
+) -> Result<(), PXError> {
+    let error = || PXError::InconsistentLengths {
+        container_length,
+        decompressed_length,
+    };
+    let body_length = (container_length as u64)
+        .checked_sub(header_length)
+        .ok_or_else(error)?;
+    if decompressed_length as u64 > body_length * 9 {
+        return Err(PXError::ImplausibleDecompressedLength {
+            container_length,
+            decompressed_length,
+        });
+    };
+    // the first command is always executed, even with a decompressed length of 0
+    let max_data_bytes = (decompressed_length as u64).max(1) + 17;
+    let max_command_bytes = max_data_bytes.div_ceil(8);
+    if body_length > max_data_bytes + max_command_bytes {
+        return Err(error());
+    };
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+/// what ``decompress_px_raw`` know after decompressing
+struct RawDecodeResult {
+    decompressed_length: u64,
+    /// the number of byte left in the file after the compressed data
+    trailing_length: u64,
+}
+
+/// a decoded command of the px stream
+#[derive(Debug, Clone, Copy)]
+// only the observers of ``decompress_px_raw_observed`` look into them
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+enum Command {
+    Literal,
+    Pattern { index: usize, nb_low: u8 },
+    BackRef { distance: usize, length: usize },
+}
+
+/// execute the commands of a px body, keeping track of the decompressed bytes back-references can copy from
+struct CommandDecoder {
+    control_flags: ControlFlags,
+    window: Window,
+}
+
+impl CommandDecoder {
+    fn new(control_flags: [u8; 9]) -> CommandDecoder {
+        CommandDecoder {
+            control_flags: ControlFlags::new(control_flags),
+            window: Window::new(),
+        }
+    }
+
+    /// the number of byte decompressed until now
+    fn len(&self) -> usize {
+        self.window.len
+    }
+
+    /// read and execute the next command from ``reader``, ``this_bit`` being its bit in the command byte.
+    /// The bytes it produce are appended to ``produced``.
+    fn execute<R: ByteSource>(
+        &mut self,
+        reader: &mut R,
+        this_bit: bool,
+        produced: &mut Vec<u8>,
+    ) -> Result<Command, PXError> {
+        let window = &mut self.window;
+        let this_byte = reader.read_byte()?;
+
+        if this_bit {
+            trace!("bit is 1: pushing 0x{:2x}", this_byte);
+            window.push(this_byte);
+            produced.push(this_byte);
+            return Ok(Command::Literal);
+        };
+
+        let (nb_high, nb_low) = arith::split_nibbles(this_byte);
+        match self.control_flags.find(nb_high) {
+            Some(ctrlflagindex) => {
+                let byte_to_add = PX_CONTROL_OPS[ctrlflagindex].apply(nb_low).ok_or(
+                    PXError::InvalidControlPattern {
+                        index: ctrlflagindex,
+                        nb_low,
+                    },
+                )?;
+                trace!("bit is 0: ctrlflagindex is {:x}, nb_high is {:x}, nb_low is {:x}, adding 0x{:2x}{:2x}", ctrlflagindex, nb_high, nb_low, byte_to_add.0, byte_to_add.1);
+                window.push(byte_to_add.0);
+                window.push(byte_to_add.1);
+                produced.push(byte_to_add.0);
+                produced.push(byte_to_add.1);
+                Ok(Command::Pattern {
+                    index: ctrlflagindex,
+                    nb_low,
+                })
+            }
+            None => {
+                let new_byte = reader.read_byte()?;
+                trace!("bit is 0: pushing from past, distance is {}, lenght is {} (nb_low:{}, nb_high:{}, new_byte:0x{:2x})", arith::backref_distance(nb_low, new_byte), arith::backref_length(nb_high), nb_low, nb_high, new_byte);
+                let (offset, lenght) =
+                    arith::decode_backref(nb_low, nb_high, new_byte, window.len)?;
+                for c in offset..(offset + lenght) {
+                    let byte = window.get(c);
+                    // the copied bytes may themselves be part of this back-reference
+                    window.push(byte);
+                    produced.push(byte);
                 }
-            };
-            bit_num += 1;
-            if result.len() >= decompressed_lenght as usize {
-                break 'main;
+                Ok(Command::BackRef {
+                    distance: arith::backref_distance(nb_low, new_byte),
+                    length: lenght,
+                })
+            }
+        }
+    }
+}
+
+/// count the bytes read from a ``ByteSource``
+struct CountingSource<'a, R: ByteSource> {
+    inner: &'a mut R,
+    count: u64,
+}
+
+impl<R: ByteSource> ByteSource for CountingSource<'_, R> {
+    fn read_byte(&mut self) -> Result<u8, PXError> {
+        let byte = self.inner.read_byte()?;
+        self.count += 1;
+        Ok(byte)
+    }
+}
+
+/// the decompression loop shared by every decompressor. The body is decoded one command byte at a time, and the end of the
+/// decompressed data (the overshoot, the output limit and the container length) is handled here.
+struct BodyDecoder {
+    decoder: CommandDecoder,
+    header: PxHeader,
+    options: DecodeOptions,
+    /// the number of byte of the body read until now, padding included
+    consumed: u64,
+    /// the number of byte of compressed data, known once finished
+    data_length: u64,
+    /// the number of byte past the decompressed length that were dropped
+    dropped: usize,
+    finished: bool,
+}
+
+impl BodyDecoder {
+    /// check that the lengths of the header are plausible, and prepare to decode its body
+    fn new(header: &PxHeader, options: &DecodeOptions) -> Result<BodyDecoder, PXError> {
+        Self::check_header(header, options)?;
+        Ok(BodyDecoder {
+            decoder: CommandDecoder::new(header.control_flags),
+            header: header.clone(),
+            options: options.clone(),
+            consumed: 0,
+            data_length: 0,
+            dropped: 0,
+            // an empty file has no body to read
+            finished: header.decompressed_length == 0,
+        })
+    }
+
+    /// check that the lengths of the header are plausible and below the output limit, before its body is decoded
+    fn check_header(header: &PxHeader, options: &DecodeOptions) -> Result<(), PXError> {
+        check_lengths(
+            header.decompressed_length,
+            header.container_length,
+            header.header_length,
+        )?;
+        options.check_output_limit(header.decompressed_length as usize)
+    }
+
+    /// true once the decompressed length is reached
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// the number of decompressed byte kept until now
+    fn output_len(&self) -> usize {
+        self.decoder.len() - self.dropped
+    }
+
+    /// decode the next command byte of ``body``, appending the bytes its commands produce to ``produced``. ``on_command`` is called after
+    /// every command with the command, the position in the output of the first byte it produced, and the bytes it produced.
+    ///
+    /// Once the decompressed length is reached, the overshoot is dropped from ``produced`` if asked, the end of the container is
+    /// checked, and ``is_finished`` become true.
+    fn decode_command_byte<R, C>(
+        &mut self,
+        body: &mut R,
+        produced: &mut Vec<u8>,
+        on_command: &mut C,
+    ) -> Result<(), PXError>
+    where
+        R: ByteSource,
+        C: FnMut(Command, usize, &[u8]) -> Result<(), PXError>,
+    {
+        let decompressed_length = self.header.decompressed_length as usize;
+        let mut body = CountingSource {
+            inner: body,
+            count: 0,
+        };
+        let command_byte = body.read_byte().map_err(truncated_body)?;
+        trace!("command byte: 0x{:x}", command_byte);
+        // the output can't reach the decompressed length during this command byte if it is far enought
+        let may_end =
+            decompressed_length.saturating_sub(self.decoder.len()) <= 8 * MAX_COMMAND_OUTPUT;
+        for this_bit in command_bits(command_byte).iter() {
+            let command_start = produced.len();
+            let command = self
+                .decoder
+                .execute(&mut body, *this_bit, produced)
+                .map_err(truncated_body)?;
+            on_command(
+                command,
+                self.decoder.len() - (produced.len() - command_start),
+                &produced[command_start..],
+            )?;
+            if may_end && self.decoder.len() >= decompressed_length {
+                let overshoot = self.decoder.len() - decompressed_length;
+                if self.options.truncate_overshoot && overshoot != 0 {
+                    trace!("dropping {} bytes past the decompressed lenght", overshoot);
+                    produced.truncate(produced.len() - overshoot);
+                    self.dropped = overshoot;
+                };
+                self.options.check_output_limit(self.output_len())?;
+                self.data_length = self.consumed + body.count;
+                trace!(
+                    "expected container lenght: {}, read: {}",
+                    self.header.container_length,
+                    self.data_length + self.header.header_length
+                );
+                if !self.options.ignore_container_length {
+                    check_container_end(&mut body, &self.header, self.data_length)?;
+                };
+                self.finished = true;
+                break;
             };
         }
-        trace!("current output size : {}", result.len());
+        self.consumed += body.count;
+        Ok(())
     }
-    trace!("decoding loop finished.");
+}
+
+#[cfg(feature = "std")]
+/// give access to the body of a px file, that start at the current position of ``file``. Also return the number of byte in the body and
+/// what follow it.
+fn body_reader<T: Read + Seek>(mut file: T) -> Result<(BufReader<Partition<T>>, u64), PXError> {
+    let current_file_position = file.stream_position()?;
+    let current_file_len = file.seek(SeekFrom::End(0))?;
+    // a stream may have been seeked past its end
+    let len = current_file_len.saturating_sub(current_file_position);
+    Ok((
+        BufReader::new(Partition::new(file, current_file_position, len)?),
+        len,
+    ))
+}
+
+/// replace the error returned when the end of the body is reached while it is still being read
+fn truncated_body(err: PXError) -> PXError {
+    match err {
+        #[cfg(feature = "std")]
+        PXError::IOError(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            PXError::TruncatedBody
+        }
+        err => err,
+    }
+}
+
+/// check that the body, of which ``consumed`` bytes were read, end where the container length indicate.
+///
+/// Some files extracted from SIR0 containers include the padding that follow them in their container length. They are accepted if the
+/// remaining bytes of the container are only padding (0xAA or 0x00), read from ``body``.
+fn check_container_end<R: ByteSource>(
+    body: &mut R,
+    header: &PxHeader,
+    consumed: u64,
+) -> Result<(), PXError> {
+    let end = header.header_length + consumed;
+    let container_length = header.container_length as u64;
+    if container_length == end {
+        return Ok(());
+    };
+    if container_length > end {
+        for _ in end..container_length {
+            match body.read_byte().map_err(truncated_body) {
+                Ok(0xAA) | Ok(0x00) => (),
+                Err(PXError::TruncatedBody) | Ok(_) => {
+                    return Err(PXError::InvalidDecompressedLength)
+                }
+                Err(err) => return Err(err),
+            };
+        }
+        debug!(
+            "the container length include {} bytes of padding",
+            container_length - end
+        );
+        return Ok(());
+    };
+    Err(PXError::InvalidDecompressedLength)
+}
+
+#[cfg(feature = "std")]
+/// decompress the body of a px file, starting at the current position of ``file``
+fn decompress_px_raw<T: Read + Seek, W: Write>(
+    file: T,
+    header: &PxHeader,
+    options: &DecodeOptions,
+    out: &mut W,
+) -> Result<RawDecodeResult, PXError> {
+    decompress_px_raw_observed(file, header, options, out, |_, _, _| Ok(()))
+}
+
+#[cfg(feature = "std")]
+/// decompress the body of a px file, calling ``on_command`` after every command with the command, the position in the output of the first
+/// byte it produced, and the bytes it produced.
+fn decompress_px_raw_observed<T, W, C>(
+    file: T,
+    header: &PxHeader,
+    options: &DecodeOptions,
+    out: &mut W,
+    mut on_command: C,
+) -> Result<RawDecodeResult, PXError>
+where
+    T: Read + Seek,
+    W: Write,
+    C: FnMut(Command, usize, &[u8]) -> Result<(), PXError>,
+{
     trace!(
-        "expected container lenght: {}, read: {}",
-        container_lenght,
-        raw_file.seek(SeekFrom::Current(0))? + 20
+        "format: {:?}, header lenght: {}",
+        header.format,
+        header.header_length
     );
+    let mut body_decoder = BodyDecoder::new(header, options)?;
+    // the bytes produced by the current command byte (at most 8 back-references)
+    let mut produced = Vec::with_capacity(8 * MAX_COMMAND_OUTPUT);
+    let (mut raw_file, body_lenght) = body_reader(file)?;
+    if body_decoder.is_finished() {
+        trace!("nothing to decompress");
+        return Ok(RawDecodeResult {
+            decompressed_length: 0,
+            trailing_length: body_lenght,
+        });
+    };
+
+    trace!("starting decompression ...");
+    while !body_decoder.is_finished() {
+        produced.clear();
+        body_decoder.decode_command_byte(&mut raw_file, &mut produced, &mut on_command)?;
+        out.write_all(&produced)?;
+        trace!("current output size : {}", body_decoder.output_len());
+    }
+    trace!("decoding loop finished.");
     trace!(
         "expected decompressed lenght: {}, real decompressed lenght: {}",
-        decompressed_lenght,
-        result.len()
+        header.decompressed_length,
+        body_decoder.decoder.len()
     );
-    if container_lenght as u64 != raw_file.seek(SeekFrom::Current(0))? + header_lenght {
-        return Err(PXError::InvalidDecompressedLength);
-    };
-    Ok(result)
+    Ok(RawDecodeResult {
+        decompressed_length: body_decoder.output_len() as u64,
+        trailing_length: body_lenght - body_decoder.data_length,
+    })
 }
 
+#[cfg(feature = "std")]
 /// check if a file is a px-compressed filed (PKDPX or AT4PX) .
 /// return true if it is one, false otherwise.
 ///
 /// It doesn't do extensive test and don't guaranty that the file is a valid PKDPX (only check the header)
 /// Also doesn't save the position of the cursor in the file
 pub fn is_px<F: Read + Seek>(file: &mut F) -> Result<bool, PXError> {
-    if file.seek(SeekFrom::End(0))? < 4 {
-        return Ok(false);
+    Ok(px_kind(file)?.is_some())
+}
+
+/// check if a file already in memory is a px-compressed file, like ``is_px``
+pub fn is_px_slice(data: &[u8]) -> Result<bool, PXError> {
+    Ok(data.len() >= 5 && (data[..5] == PKDPX_MAGIC || data[..5] == AT4PX_MAGIC))
+}
+
+#[cfg(feature = "std")]
+/// check if a file is a px-compressed file, like ``is_px``, and return which of PKDPX or AT4PX it is.
+/// return ``None`` if it isn't a px file.
+///
+/// Like ``is_px``, it only check the header, and doesn't save the position of the cursor in the file
+pub fn px_kind<F: Read + Seek>(file: &mut F) -> Result<Option<PxFormat>, PXError> {
+    // too short to contain a magic
+    if file.seek(SeekFrom::End(0))? < 5 {
+        return Ok(None);
     };
 
     file.seek(SeekFrom::Start(0))?;
@@ -239,15 +1127,16 @@ pub fn is_px<F: Read + Seek>(file: &mut F) -> Result<bool, PXError> {
     let mut header_5 = [0; 5];
     file.read_exact(&mut header_5)?;
 
-    if &header_5 == b"PKDPX" {
-        return Ok(true);
+    if header_5 == PKDPX_MAGIC {
+        return Ok(Some(PxFormat::Pkdpx));
     };
-    if &header_5 == b"AT4PX" {
-        return Ok(true);
+    if header_5 == AT4PX_MAGIC {
+        return Ok(Some(PxFormat::At4px));
     };
-    Ok(false)
+    Ok(None)
 }
 
+#[cfg(feature = "std")]
 /// use a naive compression algoritm to compress the input to a PKDPX file
 pub fn naive_compression<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError> {
     let decompressed_size = file.seek(SeekFrom::End(0))?;
@@ -259,9 +1148,7 @@ pub fn naive_compression<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError
     // container_lenght
     result.append(&mut u16::to_le_bytes(0).to_vec()); //TODO: rewrite
                                                       // control flags
-    for _ in 0..9 {
-        result.push(0);
-    }
+    result.extend_from_slice(&[0; 9]);
     // decompressed lenght
     result.append(&mut u32::to_le_bytes(decompressed_size as u32).to_vec());
 
@@ -272,7 +1159,7 @@ pub fn naive_compression<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError
         };
         result.push(px_read_u8(&mut file)?);
 
-        if file.seek(SeekFrom::Current(0))? >= decompressed_size {
+        if file.stream_position()? >= decompressed_size {
             break;
         };
         loop_nb += 1;
@@ -283,7 +1170,7 @@ pub fn naive_compression<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError
         result.push(0xAA);
     }
 
-    if container_lenght > (core::u16::MAX as usize) {
+    if container_lenght > (u16::MAX as usize) {
         return Err(PXError::FileToCompressTooLong(container_lenght));
     };
 
@@ -293,3 +1180,151 @@ pub fn naive_compression<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a PKDPX file with a literal then a pattern of two bytes, but a decompressed length of 2
+    fn overshooting_file() -> Vec<u8> {
+        let mut file = PKDPX_MAGIC.to_vec();
+        file.extend_from_slice(&23u16.to_le_bytes());
+        file.extend_from_slice(&[0, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10]);
+        file.extend_from_slice(&2u32.to_le_bytes());
+        file.extend_from_slice(&[0b1000_0000, 0x41, 0x05]);
+        file
+    }
+
+    #[cfg(feature = "std")]
+    fn decode_both_ways(options: &DecodeOptions) -> (Vec<u8>, Vec<u8>) {
+        let file = overshooting_file();
+        let written = decompress_px_with_options(Cursor::new(&file), options).unwrap();
+        let mut readed = Vec::new();
+        PxDecoder::with_options(Cursor::new(&file), options)
+            .unwrap()
+            .read_to_end(&mut readed)
+            .unwrap();
+        (written, readed)
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn overshoot_is_kept_by_default() {
+        let (written, readed) = decode_both_ways(&DecodeOptions::default());
+        assert_eq!(written, [0x41, 0x55, 0x55]);
+        assert_eq!(readed, [0x41, 0x55, 0x55]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn overshoot_is_truncated() {
+        let options = DecodeOptions {
+            truncate_overshoot: true,
+            ..Default::default()
+        };
+        let (written, readed) = decode_both_ways(&options);
+        assert_eq!(written, [0x41, 0x55]);
+        assert_eq!(readed, [0x41, 0x55]);
+    }
+
+    #[test]
+    fn slice_decompression() {
+        let file = overshooting_file();
+        assert_eq!(decompress_px_slice(&file).unwrap(), [0x41, 0x55, 0x55]);
+        assert!(is_px_slice(&file).unwrap());
+        assert!(!is_px_slice(&file[..4]).unwrap());
+    }
+
+    #[test]
+    fn slice_decompression_errors() {
+        let file = overshooting_file();
+        assert!(matches!(
+            decompress_px_slice(&file[..22]),
+            Err(PXError::TruncatedBody)
+        ));
+        assert!(matches!(
+            decompress_px_slice(&file[..10]),
+            Err(PXError::TruncatedHeader {
+                needed: 20,
+                got: 10
+            })
+        ));
+        let mut too_long = file.clone();
+        too_long.push(0x55);
+        too_long[5..7].copy_from_slice(&24u16.to_le_bytes());
+        assert!(matches!(
+            decompress_px_slice(&too_long),
+            Err(PXError::InvalidDecompressedLength)
+        ));
+    }
+
+    #[test]
+    fn slice_decompression_with_padding() {
+        let mut padded = overshooting_file();
+        padded.extend_from_slice(&[0xAA, 0xAA, 0x00]);
+        padded[5..7].copy_from_slice(&26u16.to_le_bytes());
+        assert_eq!(decompress_px_slice(&padded).unwrap(), [0x41, 0x55, 0x55]);
+    }
+
+    #[test]
+    fn control_op_out_of_range() {
+        for op in PX_CONTROL_OPS.iter() {
+            for nb_low in 16..=255 {
+                assert_eq!(op.apply(nb_low), None);
+            }
+        }
+        assert_eq!(PX_CONTROL_OPS[5].apply(0), None);
+        assert_eq!(PX_CONTROL_OPS[8].apply(15), None);
+        assert_eq!(ControlOp::new(0, Some(4), 1).apply(3), None);
+        assert_eq!(ControlOp::new(127, None, 0).apply(15), None);
+        assert_eq!(ControlOp::new(0, Some(0), 127).apply(15), None);
+        assert_eq!(ControlOp::new(-128, Some(0), -128).apply(0), None);
+    }
+
+    #[test]
+    fn first_control_op_repeat_nb_low() {
+        for nb_low in 0..16u8 {
+            let byte = nb_low << 4 | nb_low;
+            assert_eq!(PX_CONTROL_OPS[0].apply(nb_low), Some((byte, byte)));
+        }
+    }
+
+    #[test]
+    fn get_bit_msb_first() {
+        for id in 0..8 {
+            let byte = 0x80 >> id;
+            for other in 0..8 {
+                assert_eq!(get_bit(byte, other), Some(other == id));
+                assert_eq!(get_bit(!byte, other), Some(other != id));
+            }
+        }
+        assert_eq!(
+            command_bits(0b1010_0001),
+            [true, false, true, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn duplicated_control_flags() {
+        assert!(ControlFlags::new([0, 1, 2, 3, 4, 5, 6, 7, 8])
+            .validate()
+            .is_ok());
+        assert!(ControlFlags::new([0, 0x10, 0x10, 0xFF, 4, 5, 6, 7, 8])
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            ControlFlags::new([0, 1, 2, 3, 4, 5, 1, 7, 8]).validate(),
+            Err(PXError::AmbiguousControlFlags {
+                flag: 1,
+                first_index: 1,
+                second_index: 6
+            })
+        ));
+    }
+
+    #[test]
+    fn get_bit_out_of_range() {
+        assert_eq!(get_bit(0xFF, 8), None);
+        assert_eq!(get_bit(0xFF, 100), None);
+    }
+}
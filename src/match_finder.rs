@@ -0,0 +1,199 @@
+use crate::compress::{MAX_MATCH, MIN_MATCH};
+use crate::WINDOW_SIZE;
+
+/// search previous occurences of the data to compress, that the compressor can encode as back-references.
+///
+/// Before compressing some data, ``reset`` is called with it. Then ``find_match`` is called with increasing positions in this same data
+/// (some positions may be skipped).
+pub trait MatchFinder {
+    /// prepare to search matches in ``data``, forgetting the previously compressed data
+    fn reset(&mut self, data: &[u8]);
+
+    /// return the ``(distance, length)`` of the best previous occurence of the data at ``pos``. ``data[pos - distance..]`` should start with
+    /// ``length`` bytes equal to the one at ``data[pos..]`` (the two may overlap).
+    ///
+    /// The format allow a distance of 1 to 4096 and a length of 3 to 18 that doesn't go past the end of the data. A longer length is
+    /// shortened, and other invalid matches are ignored. Whether the bytes actually match isn't checked.
+    fn find_match(&mut self, data: &[u8], pos: usize) -> Option<(usize, usize)>;
+}
+
+/// restrict what a ``MatchFinder`` found to what can be encoded
+pub(crate) fn encodable_match(
+    data: &[u8],
+    pos: usize,
+    found: Option<(usize, usize)>,
+) -> Option<(usize, usize)> {
+    let (distance, length) = found?;
+    let length = length.min(MAX_MATCH).min(data.len() - pos);
+    if distance == 0 || distance > pos.min(WINDOW_SIZE) || length < MIN_MATCH {
+        None
+    } else {
+        Some((distance, length))
+    }
+}
+
+/// number of bits of the hash of the three first bytes of a match
+const HASH_BITS: u32 = 15;
+const NO_POS: usize = usize::MAX;
+
+/// find the longest previous occurence of the data at a position, using hash chains. This is the finder used by default.
+///
+/// Every position is linked to the previous one whose first three bytes have the same hash. As every
+/// encodable match start with three equal bytes, walking the chain of the current position through the whole
+/// window is guaranteed to find the longest match, and not just the first one. The closest one is prefered.
+pub struct HashChainMatchFinder {
+    /// the farthest a match can be, at most ``WINDOW_SIZE``
+    max_distance: usize,
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    /// all the position before this one are in the chains
+    inserted: usize,
+}
+
+impl HashChainMatchFinder {
+    pub fn new() -> HashChainMatchFinder {
+        Self::with_max_distance(WINDOW_SIZE)
+    }
+
+    /// a finder that only search matches at most ``max_distance`` bytes back (and never more than the 4096 bytes of the window).
+    /// It is faster, as less positions are compared, but find less matches.
+    pub fn with_max_distance(max_distance: usize) -> HashChainMatchFinder {
+        HashChainMatchFinder {
+            max_distance: max_distance.min(WINDOW_SIZE),
+            head: vec![NO_POS; 1 << HASH_BITS],
+            prev: Vec::new(),
+            inserted: 0,
+        }
+    }
+
+    fn hash(data: &[u8], pos: usize) -> usize {
+        let key = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+        (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert_until(&mut self, data: &[u8], pos: usize) {
+        while self.inserted < pos {
+            if self.inserted + MIN_MATCH <= data.len() {
+                let hash = Self::hash(data, self.inserted);
+                self.prev[self.inserted] = self.head[hash];
+                self.head[hash] = self.inserted;
+            };
+            self.inserted += 1;
+        }
+    }
+}
+
+impl Default for HashChainMatchFinder {
+    fn default() -> HashChainMatchFinder {
+        HashChainMatchFinder::new()
+    }
+}
+
+impl MatchFinder for HashChainMatchFinder {
+    fn reset(&mut self, data: &[u8]) {
+        self.head.iter_mut().for_each(|pos| *pos = NO_POS);
+        self.prev.clear();
+        self.prev.resize(data.len(), NO_POS);
+        self.inserted = 0;
+    }
+
+    fn find_match(&mut self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+        self.insert_until(data, pos);
+        let max_length = MAX_MATCH.min(data.len() - pos);
+        if max_length < MIN_MATCH {
+            return None;
+        };
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[Self::hash(data, pos)];
+        while candidate != NO_POS && pos - candidate <= self.max_distance {
+            // the copy is done byte by byte, so a match can overlap the current position
+            let length = (0..max_length)
+                .take_while(|offset| data[candidate + offset] == data[pos + offset])
+                .count();
+            if length >= MIN_MATCH && best.is_none_or(|(_, best_length)| length > best_length) {
+                best = Some((pos - candidate, length));
+                if length == max_length {
+                    break;
+                };
+            };
+            candidate = self.prev[candidate];
+        }
+        best
+    }
+}
+
+/// find the longest previous occurence of the data at a position by comparing it with every position of the window, preferring the
+/// closest one. This is slow, but simple enought to be used as a reference for other finders.
+#[derive(Debug, Default)]
+pub struct BruteForceMatchFinder;
+
+impl MatchFinder for BruteForceMatchFinder {
+    fn reset(&mut self, _data: &[u8]) {}
+
+    fn find_match(&mut self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+        let max_length = MAX_MATCH.min(data.len() - pos);
+        let mut best: Option<(usize, usize)> = None;
+        for distance in 1..=pos.min(WINDOW_SIZE) {
+            let length = (0..max_length)
+                .take_while(|offset| data[pos - distance + offset] == data[pos + offset])
+                .count();
+            if length >= MIN_MATCH && best.is_none_or(|(_, best_length)| length > best_length) {
+                best = Some((distance, length));
+            };
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_px_stats, decompress_px_slice, CompressOptions};
+    use std::io::Cursor;
+
+    fn sample(len: usize, alphabet: u32) -> Vec<u8> {
+        let mut seed = 0xDEAD_BEEFu32;
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((seed >> 16) % alphabet) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeated_pattern_is_back_referenced() {
+        let pattern = b"0123456789ABCDEF";
+        let data: Vec<u8> = pattern.iter().cycle().take(16 * 10).copied().collect();
+
+        let mut finder = HashChainMatchFinder::new();
+        finder.reset(&data);
+        assert_eq!(finder.find_match(&data, 16), Some((16, MAX_MATCH)));
+
+        let (compressed, stats) =
+            compress_px_stats(Cursor::new(&data), &CompressOptions::default()).unwrap();
+        assert!(stats.literal_bytes <= 16);
+        assert!(stats.copy_commands >= (data.len() - 16) / MAX_MATCH);
+        assert_eq!(decompress_px_slice(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn hash_chain_match_brute_force() {
+        for alphabet in [2, 4, 16, 256] {
+            let data = sample(6000, alphabet);
+            let mut hash_chain = HashChainMatchFinder::new();
+            let mut brute_force = BruteForceMatchFinder;
+            hash_chain.reset(&data);
+            brute_force.reset(&data);
+            for pos in 0..data.len() {
+                assert_eq!(
+                    hash_chain.find_match(&data, pos),
+                    brute_force.find_match(&data, pos),
+                    "alphabet {}, position {}",
+                    alphabet,
+                    pos
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{decompress_px, PXError, AT4PX_MAGIC, PKDPX_MAGIC};
+use std::io::{Read, Seek, SeekFrom};
+
+/// a compression container used by the pokemon mystery dungeon games, identified by its magic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PmdCompression {
+    /// ``PKDPX``, px compression with a 32 bit decompressed length
+    Pkdpx,
+    /// ``AT4PX``, px compression with a 16 bit decompressed length
+    At4px,
+    /// ``AT3PX``
+    At3px,
+    /// ``ATUPX``
+    Atupx,
+    /// ``AT4PN``, that store the data without compression
+    At4pn,
+}
+
+impl PmdCompression {
+    /// all the known containers
+    pub const ALL: [PmdCompression; 5] = [
+        Self::Pkdpx,
+        Self::At4px,
+        Self::At3px,
+        Self::Atupx,
+        Self::At4pn,
+    ];
+
+    /// the five bytes at the start of a file using this container
+    pub fn magic(self) -> &'static [u8; 5] {
+        match self {
+            Self::Pkdpx => &PKDPX_MAGIC,
+            Self::At4px => &AT4PX_MAGIC,
+            Self::At3px => b"AT3PX",
+            Self::Atupx => b"ATUPX",
+            Self::At4pn => b"AT4PN",
+        }
+    }
+
+    /// return the container that use this magic, if any
+    pub fn from_magic(magic: &[u8; 5]) -> Option<PmdCompression> {
+        Self::ALL
+            .iter()
+            .find(|compression| compression.magic() == magic)
+            .copied()
+    }
+
+    /// true if this crate can decompress this container
+    pub fn is_supported(self) -> bool {
+        matches!(self, Self::Pkdpx | Self::At4px)
+    }
+}
+
+/// read the magic of the file, and return which container it is, if it is a known one.
+///
+/// It only check the magic. It doesn't restore the position of the cursor.
+pub fn detect_compression<F: Read + Seek>(file: &mut F) -> Result<Option<PmdCompression>, PXError> {
+    if file.seek(SeekFrom::End(0))? < 5 {
+        return Ok(None);
+    };
+    file.seek(SeekFrom::Start(0))?;
+    let mut magic = [0; 5];
+    file.read_exact(&mut magic)?;
+    Ok(PmdCompression::from_magic(&magic))
+}
+
+/// decompress a file using any of the ``PmdCompression`` container, based on its magic.
+///
+/// Only the px containers (PKDPX and AT4PX) are currently supported, the others return ``PXError::UnsupportedCompression``.
+pub fn decompress_any<F: Read + Seek>(mut file: F) -> Result<Vec<u8>, PXError> {
+    match detect_compression(&mut file)? {
+        Some(compression) if !compression.is_supported() => {
+            Err(PXError::UnsupportedCompression(compression))
+        }
+        // the px decoder report unknown magic by itself
+        _ => decompress_px(file),
+    }
+}
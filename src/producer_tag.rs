@@ -0,0 +1,83 @@
+use crate::{PXError, PxHeader};
+use std::io::{Read, Seek};
+
+/// the maximum length of a producer tag. At least one control flag should stay available for patterns.
+pub const MAX_PRODUCER_TAG_LEN: usize = 8;
+
+/// the value of the control flags that aren't used (for example by ``choose_control_flags``). It never match, and isn't allowed in a
+/// producer tag, so it is never mistaken for one.
+pub(crate) const UNUSED_FLAG: u8 = 0x10;
+
+/// store ``tag`` in the last control flags.
+///
+/// A control flag is compared with the high nibble of a command byte, so a flag of 0x10 or more never match and is never used by
+/// the decompressor. The tag thus doesn't change how the file decompress, as long as every byte of it is more than 0x10 (every printable
+/// ASCII character is, 0x10 being reserved for the unused flags). The patterns of the flags it overwrite can't be used anymore.
+pub(crate) fn stamp_producer_tag(control_flags: &mut [u8; 9], tag: &[u8]) -> Result<(), PXError> {
+    if tag.len() > MAX_PRODUCER_TAG_LEN {
+        return Err(PXError::InvalidProducerTag(
+            "the producer tag is longer than 8 bytes",
+        ));
+    };
+    if tag.iter().any(|byte| *byte <= UNUSED_FLAG) {
+        return Err(PXError::InvalidProducerTag(
+            "every byte of the producer tag should be more than 0x10",
+        ));
+    };
+    control_flags[9 - tag.len()..].copy_from_slice(tag);
+    Ok(())
+}
+
+/// read the producer tag stored in the control flags of a px file by ``CompressOptions::producer_tag`` (the longest run of flags of more
+/// than 0x10 at the end of the flags, as the unused flags are 0x10). Return ``None`` if there is none, like in the files of the official games.
+pub fn read_producer_tag<F: Read + Seek>(mut file: F) -> Result<Option<Vec<u8>>, PXError> {
+    let header = PxHeader::parse(&mut file)?;
+    let flags = header.control_flags;
+    let tag_start = flags
+        .iter()
+        .rposition(|flag| *flag <= UNUSED_FLAG)
+        .map_or(0, |pos| pos + 1);
+    if tag_start == flags.len() {
+        Ok(None)
+    } else {
+        Ok(Some(flags[tag_start..].to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{choose_control_flags, compress_px_with_options, CompressOptions};
+    use std::io::Cursor;
+
+    fn compress_with_chosen_flags(data: &[u8], producer_tag: Option<Vec<u8>>) -> Vec<u8> {
+        let options = CompressOptions {
+            control_flags: Some(choose_control_flags(data)),
+            producer_tag,
+            ..Default::default()
+        };
+        compress_px_with_options(Cursor::new(data), &options).unwrap()
+    }
+
+    #[test]
+    fn tag_roundtrip_with_chosen_flags() {
+        let data = b"some data to compress, with some data repeated".repeat(10);
+        assert!(choose_control_flags(&data).contains(&UNUSED_FLAG));
+
+        let untagged = compress_with_chosen_flags(&data, None);
+        assert_eq!(read_producer_tag(Cursor::new(&untagged)).unwrap(), None);
+
+        let tagged = compress_with_chosen_flags(&data, Some(b"AB".to_vec()));
+        assert_eq!(
+            read_producer_tag(Cursor::new(&tagged)).unwrap(),
+            Some(b"AB".to_vec())
+        );
+    }
+
+    #[test]
+    fn unused_flag_is_not_a_tag_byte() {
+        let mut control_flags = [0; 9];
+        assert!(stamp_producer_tag(&mut control_flags, &[b'A', UNUSED_FLAG]).is_err());
+        assert!(stamp_producer_tag(&mut control_flags, &[b'A', UNUSED_FLAG + 1]).is_ok());
+    }
+}
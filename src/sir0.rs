@@ -0,0 +1,153 @@
+use crate::PXError;
+use std::ops::Range;
+
+/// the size of the SIR0 header: the magic, the data pointer, the pointer list pointer and 4 bytes of padding
+const SIR0_HEADER_LENGTH: usize = 16;
+
+fn read_u32_at(data: &[u8], pos: usize) -> Result<usize, PXError> {
+    let bytes = pos
+        .checked_add(4)
+        .and_then(|end| data.get(pos..end))
+        .ok_or(PXError::InvalidSir0("a pointer is outside of the file"))?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+/// decode the pointer list of a SIR0 file, returning the position of every pointer in the file.
+///
+/// Each entry is the distance from the previous pointer, stored in big-endian 7-bit groups (the high bit
+/// indicating another group follow). The list end with a 0.
+fn decode_pointer_list(data: &[u8], list_start: usize) -> Result<Vec<usize>, PXError> {
+    let mut pointers = Vec::new();
+    let mut current: usize = 0;
+    let mut accumulator: usize = 0;
+    for byte in data.get(list_start..).ok_or(PXError::InvalidSir0(
+        "the pointer list is outside of the file",
+    ))? {
+        if accumulator > usize::MAX >> 7 {
+            return Err(PXError::InvalidSir0(
+                "an entry of the pointer list is too big",
+            ));
+        };
+        accumulator = (accumulator << 7) | (byte & 0x7F) as usize;
+        if byte & 0x80 == 0 {
+            if accumulator == 0 {
+                return Ok(pointers);
+            };
+            current = current
+                .checked_add(accumulator)
+                .ok_or(PXError::InvalidSir0("a pointer is outside of the file"))?;
+            pointers.push(current);
+            accumulator = 0;
+        };
+    }
+    Err(PXError::InvalidSir0("the pointer list isn't terminated"))
+}
+
+/// return the byte range of every object referenced by a pointer in a (decompressed) SIR0 file, sorted by position.
+///
+/// The objects are the targets of the pointers listed in the pointer list, plus the data pointed by the header.
+/// The two pointers of the header itself (at offsets 4 and 8) are not objects. An object extend until the start of the
+/// next one, and the last one until the start of the pointer list.
+pub fn slice_sir0_objects(decompressed: &[u8]) -> Result<Vec<Range<usize>>, PXError> {
+    if decompressed.len() < SIR0_HEADER_LENGTH || &decompressed[0..4] != b"SIR0" {
+        return Err(PXError::InvalidSir0("the SIR0 header is missing"));
+    };
+    let data_pointer = read_u32_at(decompressed, 4)?;
+    let list_start = read_u32_at(decompressed, 8)?;
+    if list_start < SIR0_HEADER_LENGTH || list_start > decompressed.len() {
+        return Err(PXError::InvalidSir0(
+            "the pointer list is outside of the file",
+        ));
+    };
+
+    let mut targets = vec![data_pointer];
+    for pointer in decode_pointer_list(decompressed, list_start)? {
+        if pointer == 4 || pointer == 8 {
+            continue;
+        };
+        if pointer >= list_start {
+            return Err(PXError::InvalidSir0(
+                "a pointer is stored in the pointer list",
+            ));
+        };
+        targets.push(read_u32_at(decompressed, pointer)?);
+    }
+    if targets
+        .iter()
+        .any(|target| *target < SIR0_HEADER_LENGTH || *target >= list_start)
+    {
+        return Err(PXError::InvalidSir0("a pointer point outside of the data"));
+    };
+    targets.sort_unstable();
+    targets.dedup();
+
+    let ends = targets.iter().skip(1).copied().chain(Some(list_start));
+    Ok(targets
+        .iter()
+        .zip(ends)
+        .map(|(start, end)| *start..end)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u32(file: &mut [u8], pos: usize, value: u32) {
+        file[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn objects_of_a_sub_header() {
+        // the header point to a sub-header at 0x20, which point to the objects at 0x10 and 0x28. The last
+        // pointer (at 0x2C) point to 0x10 again.
+        let mut file = vec![0; 0x30];
+        file[0..4].copy_from_slice(b"SIR0");
+        write_u32(&mut file, 4, 0x20);
+        write_u32(&mut file, 8, 0x30);
+        write_u32(&mut file, 0x20, 0x10);
+        write_u32(&mut file, 0x24, 0x28);
+        write_u32(&mut file, 0x2C, 0x10);
+        // pointers at 4, 8, 0x20, 0x24 and 0x2C
+        file.extend_from_slice(&[4, 4, 0x18, 4, 8, 0]);
+
+        assert_eq!(
+            slice_sir0_objects(&file).unwrap(),
+            vec![0x10..0x20, 0x20..0x28, 0x28..0x30]
+        );
+    }
+
+    #[test]
+    fn pointer_list_overflow() {
+        // three entries of 9 groups (63 bits), whose sum doesn't fit in 64 bits
+        let mut list = Vec::new();
+        for _ in 0..3 {
+            list.extend_from_slice(&[0xFF; 8]);
+            list.push(0x7F);
+        }
+        list.push(0);
+        assert!(matches!(
+            decode_pointer_list(&list, 0),
+            Err(PXError::InvalidSir0(_))
+        ));
+    }
+
+    #[test]
+    fn pointer_list_too_long_entry() {
+        let mut list = vec![0xFF; 12];
+        list.push(0x7F);
+        list.push(0);
+        assert!(matches!(
+            decode_pointer_list(&list, 0),
+            Err(PXError::InvalidSir0(_))
+        ));
+    }
+
+    #[test]
+    fn read_at_the_end_of_the_address_space() {
+        assert!(matches!(
+            read_u32_at(&[0; 8], usize::MAX - 1),
+            Err(PXError::InvalidSir0(_))
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{decompress_px_raw_observed, Command, DecodeOptions, PXError, PxHeader};
+use std::io::{self, Read, Seek, Write};
+
+fn write_bytes(out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    write!(out, "[")?;
+    for (pos, byte) in bytes.iter().enumerate() {
+        if pos != 0 {
+            write!(out, ",")?;
+        };
+        write!(out, "{}", byte)?;
+    }
+    write!(out, "]")
+}
+
+fn write_command(
+    out: &mut dyn Write,
+    command: Command,
+    output_offset: usize,
+    output: &[u8],
+) -> io::Result<()> {
+    match command {
+        Command::Literal => write!(out, "{{\"type\":\"literal\"")?,
+        Command::Pattern { index, nb_low } => write!(
+            out,
+            "{{\"type\":\"pattern\",\"control_flag\":{},\"nb_low\":{}",
+            index, nb_low
+        )?,
+        Command::BackRef { distance, length } => write!(
+            out,
+            "{{\"type\":\"back_reference\",\"distance\":{},\"length\":{}",
+            distance, length
+        )?,
+    };
+    write!(out, ",\"output_offset\":{},\"output\":", output_offset)?;
+    write_bytes(out, output)?;
+    write!(out, "}}")
+}
+
+/// decompress a pkdpx or at4px file, and write to ``out`` a JSON array describing every command of the file, in order.
+///
+/// Each element is an object with a ``type`` (``literal``, ``pattern`` or ``back_reference``), the offset in the decompressed data of
+/// the first byte it produced (``output_offset``) and the produced bytes (``output``). Patterns also have the index of their ``control_flag``
+/// and their ``nb_low``, and back-references their ``distance`` and ``length``.
+///
+/// The array is written as the file is decompressed, one command per line, so nothing but the back-reference window is kept in memory.
+/// If an error happen, the array is left unterminated.
+pub fn decompress_px_trace_json<F: Read + Seek>(
+    mut file: F,
+    out: &mut dyn Write,
+) -> Result<(), PXError> {
+    let options = DecodeOptions::default();
+    let header = PxHeader::parse_with_options(&mut file, &options)?;
+    write!(out, "[")?;
+    let mut first = true;
+    decompress_px_raw_observed(
+        file,
+        &header,
+        &options,
+        &mut io::sink(),
+        |command, output_offset, output| {
+            writeln!(out, "{}", if first { "" } else { "," })?;
+            first = false;
+            write_command(out, command, output_offset, output)?;
+            Ok(())
+        },
+    )?;
+    writeln!(out, "\n]")?;
+    Ok(())
+}
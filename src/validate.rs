@@ -0,0 +1,80 @@
+use crate::{
+    arith, body_reader, check_container_end, command_bits, px_read_u8, truncated_body, BodyDecoder,
+    ControlFlags, DecodeOptions, PXError, PxHeader, PX_CONTROL_OPS,
+};
+use std::io::{Read, Seek};
+
+/// check that a pkdpx or at4px file would decompress without error, without producing its content.
+///
+/// The command stream is walked while only counting the number of decompressed bytes, which is enought to check that every
+/// back-reference stay within the already decompressed data, and that the file end where its header say it does.
+/// This is faster than a full decompression, but doesn't guarantee that the content is what was intended.
+pub fn validate_backrefs<F: Read + Seek>(mut file: F) -> Result<(), PXError> {
+    let header = PxHeader::parse(&mut file)?;
+    BodyDecoder::check_header(&header, &DecodeOptions::default())?;
+    let control_flags = ControlFlags::new(header.control_flags);
+    let (mut raw_file, _) = body_reader(file)?;
+
+    if header.decompressed_length == 0 {
+        return Ok(());
+    };
+    let mut output_len = 0;
+    'main: loop {
+        for this_bit in command_bits(px_read_u8(&mut raw_file).map_err(truncated_body)?).iter() {
+            let this_byte = px_read_u8(&mut raw_file).map_err(truncated_body)?;
+            if *this_bit {
+                output_len += 1;
+            } else {
+                let (nb_high, nb_low) = arith::split_nibbles(this_byte);
+                match control_flags.find(nb_high) {
+                    Some(index) => {
+                        PX_CONTROL_OPS[index]
+                            .apply(nb_low)
+                            .ok_or(PXError::InvalidControlPattern { index, nb_low })?;
+                        output_len += 2;
+                    }
+                    None => {
+                        let new_byte = px_read_u8(&mut raw_file).map_err(truncated_body)?;
+                        let (_, len) =
+                            arith::decode_backref(nb_low, nb_high, new_byte, output_len)?;
+                        output_len += len;
+                    }
+                }
+            };
+            if output_len >= header.decompressed_length as usize {
+                break 'main;
+            };
+        }
+    }
+
+    let consumed = raw_file.stream_position()?;
+    check_container_end(&mut raw_file, &header, consumed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_px_slice, PKDPX_MAGIC};
+    use std::io::Cursor;
+
+    #[test]
+    fn valid_file() {
+        let data: Vec<u8> = (0..3000u32).map(|i| (i * 5 % 19) as u8).collect();
+        let compressed = compress_px_slice(&data).unwrap();
+        assert!(validate_backrefs(Cursor::new(&compressed)).is_ok());
+    }
+
+    #[test]
+    fn back_reference_before_the_start() {
+        // a literal, then a back-reference of 3 bytes at a distance of 2
+        let mut file = PKDPX_MAGIC.to_vec();
+        file.extend_from_slice(&24u16.to_le_bytes());
+        file.extend_from_slice(&[0x10; 9]);
+        file.extend_from_slice(&4u32.to_le_bytes());
+        file.extend_from_slice(&[0b1000_0000, 0x41, 0x0F, 0xFE]);
+        assert!(matches!(
+            validate_backrefs(Cursor::new(&file)),
+            Err(PXError::InvalidBackReference { offset: -1, len: 3 })
+        ));
+    }
+}